@@ -0,0 +1,246 @@
+//! The server's own paddle, for players who have nobody to play against.
+//!
+//! The bot is a paddle owned by [`Owner::Ai`] instead of a peer. Goals,
+//! scores and wins count for it like for anyone else. It gives its paddle up
+//! as soon as a real peer wants it.
+//!
+//! [`Owner::Ai`]: crate::world::Owner::Ai
+
+use crate::{
+    log::Logger,
+    network::{NetworkSystem, Transport},
+    rooms::RoomId,
+    world::World,
+};
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+
+/// Where a bot is steering its paddle's center, and how long ago it last
+/// looked at the ball to decide.
+struct Aim {
+    y: f32,
+    age: f32,
+}
+
+pub struct BotSystem {
+    wait: Option<Duration>,
+    reaction: f32,
+    max_speed: f32,
+    waiting_since: HashMap<RoomId, Instant>,
+    aims: HashMap<RoomId, Aim>,
+    logger: Logger,
+}
+
+impl BotSystem {
+    /// A bot that joins lone players after `wait`, if given, looks at the
+    /// ball every `reaction` and moves at most `max_speed` units per second.
+    pub fn new(wait: Option<Duration>, reaction: Duration, max_speed: f32) -> Self {
+        BotSystem {
+            wait,
+            reaction: reaction.as_secs_f32(),
+            max_speed,
+            waiting_since: HashMap::new(),
+            aims: HashMap::new(),
+            logger: Logger::stdout(),
+        }
+    }
+
+    /// Sends this system's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// Puts the bot on the free side of `world` and introduces it to the
+    /// player already there.
+    pub fn join<T: Transport>(
+        &mut self,
+        room: RoomId,
+        world: &mut World,
+        network_system: &mut NetworkSystem<T>,
+    ) -> io::Result<()> {
+        world.create_bot();
+        self.waiting_since.remove(&room);
+        self.aims.remove(&room);
+        self.logger.log(format_args!("Bot joined room {}", room));
+        network_system.send_opponents(world)?;
+        network_system.send_score(world)
+    }
+
+    /// Takes the bot out of `world`, if it is playing there, so a joining
+    /// player can have its paddle. The ball goes with it, so the new match
+    /// starts from a fresh serve.
+    pub fn make_way(&mut self, room: RoomId, world: &mut World) {
+        if world.remove_bot().is_some() {
+            self.aims.remove(&room);
+            self.logger
+                .log(format_args!("Bot left room {} to a player", room));
+        }
+    }
+
+    /// Brings the bot in for a player who has waited longer than `wait` for
+    /// an opponent.
+    pub fn check<T: Transport>(
+        &mut self,
+        room: RoomId,
+        world: &mut World,
+        network_system: &mut NetworkSystem<T>,
+        now: Instant,
+    ) -> io::Result<()> {
+        if world.render_components.len() != 1 {
+            self.waiting_since.remove(&room);
+            return Ok(());
+        }
+
+        let wait = match self.wait {
+            Some(wait) => wait,
+            None => return Ok(()),
+        };
+        let since = *self.waiting_since.entry(room).or_insert(now);
+        if now.duration_since(since) < wait {
+            return Ok(());
+        }
+
+        self.join(room, world, network_system)
+    }
+
+    /// Drops the wait and aim kept for `room`, once the room has closed.
+    pub fn forget(&mut self, room: RoomId) {
+        self.waiting_since.remove(&room);
+        self.aims.remove(&room);
+    }
+
+    /// Every room this system keeps a wait or an aim for.
+    pub fn rooms(&self) -> impl Iterator<Item = RoomId> + '_ {
+        self.waiting_since.keys().chain(self.aims.keys()).copied()
+    }
+
+    /// Steers the bot's paddle in `world`, if it has one, toward where the
+    /// ball was when it last looked. Sets the paddle's velocity only; the
+    /// control system moves it like any other paddle.
+    pub fn update(&mut self, room: RoomId, world: &mut World, dt: f32) {
+        let bot = match world.bot_entity() {
+            Some(id) => id,
+            None => {
+                self.aims.remove(&room);
+                return;
+            }
+        };
+
+        let aim = self.aims.entry(room).or_insert(Aim {
+            y: world.field.height / 2.,
+            age: f32::INFINITY,
+        });
+        aim.age += dt;
+        if aim.age >= self.reaction {
+            if let Some(ball) = world
+                .render_components
+                .iter()
+                .find(|renderable| renderable.is_ball())
+            {
+                aim.y = ball.y + ball.height / 2.;
+            }
+            aim.age = 0.;
+        }
+
+        if let Some(index) = world
+            .render_components
+            .iter()
+            .position(|renderable| renderable.id == bot)
+        {
+            let paddle = &world.render_components[index];
+            let offset = aim.y - (paddle.y + paddle.height / 2.);
+            world.speed_components[index].dy = (offset / dt).clamp(-self.max_speed, self.max_speed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collision::CollisionSystem,
+        control::ControlSystem,
+        network::MemoryTransport,
+        physics::{Field, NOMINAL_DT, PADDLE_SPEED_UPS},
+        world::Side,
+    };
+    use std::net::SocketAddr;
+
+    const ROOM: RoomId = RoomId(0);
+
+    /// A player on the left, the bot on the right and a ball served straight
+    /// at the bot from the center, `y` down the field.
+    fn bot_match(y: f32) -> World {
+        let mut world = World::new(Field::default(), 300.);
+        let source = SocketAddr::from(([127, 0, 0, 1], 1000));
+        world.create_player(source, None, None, Instant::now());
+        world.create_bot();
+        world.create_ball();
+        world.render_components[2].y = y;
+        world
+    }
+
+    #[test]
+    fn the_bot_stays_in_the_field_and_returns_straight_balls() {
+        let field = Field::default();
+        for y in [0., 150., 300., 450., field.height - 20.] {
+            let mut world = bot_match(y);
+            let mut bot_system = BotSystem::new(None, Duration::from_millis(150), PADDLE_SPEED_UPS);
+            let control_system = ControlSystem::new(0.1, 2., PADDLE_SPEED_UPS);
+            let mut collision_system = CollisionSystem::new();
+            let mut returns = 0;
+
+            for tick in 0..600 {
+                let was_incoming = world.speed_components[2].dx > 0.;
+                bot_system.update(ROOM, &mut world, NOMINAL_DT);
+                control_system.step(&mut world, NOMINAL_DT);
+                collision_system.resolve(&mut world, NOMINAL_DT);
+                let scorer = collision_system.ball_out_of_bounds(&mut world);
+                assert_ne!(scorer, Some(Side::Left), "from {} at tick {}", y, tick);
+                if scorer.is_none() && was_incoming && world.speed_components[2].dx < 0. {
+                    returns += 1;
+                }
+
+                let bot = &world.render_components[1];
+                assert!(
+                    bot.y >= 0. && bot.y + bot.height <= field.height,
+                    "bot at {} from {} at tick {}",
+                    bot.y,
+                    y,
+                    tick
+                );
+            }
+            assert!(returns >= 1, "never returned the ball from {}", y);
+        }
+    }
+
+    #[test]
+    fn a_closed_room_leaves_nothing_behind() {
+        // The player has left a solo match, so only the bot is left.
+        let mut world = bot_match(300.);
+        let source = SocketAddr::from(([127, 0, 0, 1], 1000));
+        world.remove_player(source);
+        let mut bot_system = BotSystem::new(
+            Some(Duration::from_secs(1)),
+            Duration::from_millis(150),
+            PADDLE_SPEED_UPS,
+        );
+        let (transport, _peer) = MemoryTransport::new();
+        let now = Instant::now();
+        let mut network_system = NetworkSystem::new(transport, now);
+
+        bot_system
+            .check(ROOM, &mut world, &mut network_system, now)
+            .unwrap();
+        bot_system.update(ROOM, &mut world, NOMINAL_DT);
+        assert!(bot_system.waiting_since.contains_key(&ROOM));
+        assert!(bot_system.aims.contains_key(&ROOM));
+
+        bot_system.forget(ROOM);
+        assert!(bot_system.waiting_since.is_empty());
+        assert!(bot_system.aims.is_empty());
+    }
+}
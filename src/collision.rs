@@ -0,0 +1,475 @@
+//! Ball movement against the walls, the paddles and the goal lines.
+
+use crate::{
+    physics::{Field, BOUNCE_ENGLISH},
+    world::{Renderable, Side, Speed, World},
+};
+
+#[derive(Default)]
+pub struct CollisionSystem;
+
+impl CollisionSystem {
+    pub fn new() -> Self {
+        CollisionSystem
+    }
+
+    /// Bounces the ball off the walls and paddles after it has moved by its
+    /// velocity over `dt`.
+    pub fn resolve(&mut self, world: &mut World, dt: f32) {
+        let ball_index = match world
+            .render_components
+            .iter()
+            .position(|renderable| renderable.is_ball())
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let ball_id = world.render_components[ball_index].id;
+        let field = world.field;
+        let speed = match world
+            .speed_components
+            .iter_mut()
+            .find(|speed| speed.id == ball_id)
+        {
+            Some(speed) => speed,
+            None => return,
+        };
+
+        // Split around the ball so it can be moved while the paddles on
+        // either side of it are only read.
+        let (before, rest) = world.render_components.split_at_mut(ball_index);
+        let (ball, after) = rest.split_first_mut().unwrap();
+        let previous = (ball.x - speed.dx * dt, ball.y - speed.dy * dt);
+
+        self.reflect_off_walls(ball, speed, &field);
+
+        // A ball that was already past a goal plane when the step began has
+        // scored; never let a paddle that overlaps the line reflect it back
+        // into play. One that only crosses the plane during the step still
+        // meets any paddle face on its way, since every face is in front of
+        // the goal lines, but merely overlapping a paddle beyond the line
+        // doesn't save it.
+        if self.past_goal_line(previous.0, ball.width, &field) {
+            return;
+        }
+        let past_goal = self.goal(ball, &field);
+
+        if let Some(paddle) = before.iter().chain(after.iter()).find(|player| {
+            self.crossed_face(player, ball, previous)
+                || (!past_goal && self.overlapping(player, ball))
+        }) {
+            self.bounce(paddle, ball, speed, previous.0);
+        }
+    }
+
+    fn reflect_off_walls(&mut self, ball: &mut Renderable, velocity: &mut Speed, field: &Field) {
+        if ball.y < 0. {
+            ball.y = -ball.y;
+            velocity.dy = velocity.dy.abs();
+        } else if ball.y + ball.height > field.height {
+            ball.y = 2. * (field.height - ball.height) - ball.y;
+            velocity.dy = -velocity.dy.abs();
+        }
+        ball.y = ball.y.clamp(0., field.height - ball.height);
+    }
+
+    fn overlapping(&self, player: &Renderable, ball: &Renderable) -> bool {
+        player.x <= ball.x + ball.width
+            && player.x + player.width >= ball.x
+            && player.y <= ball.y + ball.height
+            && player.y + player.height >= ball.y
+    }
+
+    /// Whether the ball passed through the face of the paddle it was moving
+    /// towards since `previous`, level with the paddle when it did, so a ball
+    /// covering more than a paddle's width in one step still hits it.
+    fn crossed_face(&self, player: &Renderable, ball: &Renderable, previous: (f32, f32)) -> bool {
+        let (previous_x, previous_y) = previous;
+        let (face, from, to) = if ball.x > previous_x {
+            (player.x, previous_x + ball.width, ball.x + ball.width)
+        } else {
+            (player.x + player.width, previous_x, ball.x)
+        };
+        let crossed = (from <= face && to >= face) || (from >= face && to <= face);
+        if !crossed {
+            return false;
+        }
+
+        let t = if to == from {
+            0.
+        } else {
+            (face - from) / (to - from)
+        };
+        let y = previous_y + (ball.y - previous_y) * t;
+        player.y <= y + ball.height && player.y + player.height >= y
+    }
+
+    /// Resets a ball that has left the field and returns the side that
+    /// scored. The new ball is served towards the side that conceded.
+    pub fn ball_out_of_bounds(&mut self, world: &mut World) -> Option<Side> {
+        let field = world.field;
+        let serve_speed_ups = world.serve_speed_ups;
+        let ball = world
+            .render_components
+            .iter_mut()
+            .find(|renderable| renderable.is_ball())?;
+        if !self.goal(ball, &field) {
+            return None;
+        }
+
+        let scorer = if ball.x < 0. { Side::Right } else { Side::Left };
+        let speed = world
+            .speed_components
+            .iter_mut()
+            .find(|speed| speed.id == ball.id)?;
+        self.new_ball(ball, speed, scorer, &field, serve_speed_ups);
+        Some(scorer)
+    }
+
+    /// Whether the ball's leading edge has crossed either goal line. Both
+    /// sides use the same rule: the left edge past `x = 0`, or the right
+    /// edge past `x = width`.
+    fn goal(&self, ball: &Renderable, field: &Field) -> bool {
+        self.past_goal_line(ball.x, ball.width, field)
+    }
+
+    /// Whether a ball `width` wide with its left edge at `x` is past either
+    /// goal line, by the rule [`CollisionSystem::goal`] describes.
+    fn past_goal_line(&self, x: f32, width: f32, field: &Field) -> bool {
+        x < 0. || x + width > field.width
+    }
+
+    /// Sends the ball back the way it came, placed just outside the paddle so
+    /// it can't be caught inside it on the next step, with some dy added
+    /// depending on how far from the paddle's center it hit.
+    fn bounce(
+        &mut self,
+        paddle: &Renderable,
+        ball: &mut Renderable,
+        velocity: &mut Speed,
+        previous_x: f32,
+    ) {
+        let came_from_left = previous_x + ball.width / 2. < paddle.x + paddle.width / 2.;
+        let speed = velocity.dx.abs();
+        if came_from_left {
+            ball.x = paddle.x - ball.width;
+            velocity.dx = -speed;
+        } else {
+            ball.x = paddle.x + paddle.width;
+            velocity.dx = speed;
+        }
+
+        let offset = (ball.y + ball.height / 2.) - (paddle.y + paddle.height / 2.);
+        let offset = (offset / (paddle.height / 2.)).clamp(-1., 1.);
+        velocity.dy = (velocity.dy + offset * BOUNCE_ENGLISH * speed).clamp(-speed, speed);
+    }
+
+    fn new_ball(
+        &mut self,
+        ball: &mut Renderable,
+        velocity: &mut Speed,
+        scorer: Side,
+        field: &Field,
+        serve_speed_ups: f32,
+    ) {
+        ball.x = field.width / 2.;
+        ball.y = field.height / 2.;
+        velocity.dx = match scorer {
+            Side::Left => serve_speed_ups,
+            Side::Right => -serve_speed_ups,
+        };
+        velocity.dy = 0.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        control::ControlSystem,
+        physics::{
+            BALL_SIZE, NOMINAL_DT, PADDLE_HEIGHT, PADDLE_MARGIN, PADDLE_SPEED_UPS, PADDLE_WIDTH,
+        },
+        protocol::EntityKind,
+        world::EntityId,
+    };
+    use std::{net::SocketAddr, time::Instant};
+
+    /// A world with a player on each side and a ball at `x`, 300 units
+    /// down, moving at `dx`.
+    fn world_with_ball(x: f32, dx: f32) -> World {
+        world_with_ball_on(Field::default(), x, dx)
+    }
+
+    fn world_with_ball_on(field: Field, x: f32, dx: f32) -> World {
+        let mut world = World::new(field, 1200.);
+        let now = Instant::now();
+        for port in [1000, 1001] {
+            let source = SocketAddr::from(([127, 0, 0, 1], port));
+            world.create_player(source, None, None, now);
+        }
+        world.create_ball();
+        let ball = world.render_components.last_mut().unwrap();
+        ball.x = x;
+        ball.y = 300.;
+        world.speed_components.last_mut().unwrap().dx = dx;
+        world
+    }
+
+    fn ball(world: &World) -> &Renderable {
+        world
+            .render_components
+            .iter()
+            .find(|renderable| renderable.is_ball())
+            .unwrap()
+    }
+
+    #[test]
+    fn a_ball_touching_a_goal_line_is_in_play() {
+        let field = Field::default();
+        for x in [0., field.width - BALL_SIZE] {
+            let mut world = world_with_ball(x, 0.);
+            assert_eq!(CollisionSystem::new().ball_out_of_bounds(&mut world), None);
+        }
+    }
+
+    #[test]
+    fn both_goals_count_the_leading_edge() {
+        let field = Field::default();
+        let mut left = world_with_ball(-0.01, 0.);
+        assert_eq!(
+            CollisionSystem::new().ball_out_of_bounds(&mut left),
+            Some(Side::Right)
+        );
+        let mut right = world_with_ball(field.width - BALL_SIZE + 0.01, 0.);
+        assert_eq!(
+            CollisionSystem::new().ball_out_of_bounds(&mut right),
+            Some(Side::Left)
+        );
+    }
+
+    #[test]
+    fn a_paddle_on_the_goal_line_cannot_save_a_ball_past_it() {
+        let field = Field::default();
+        // Each paddle pushed onto its own goal line, and the ball half over
+        // that line, overlapping the paddle, on its way out.
+        for (side, paddle_x, ball_x, dx) in [
+            (Side::Left, 0., -BALL_SIZE / 2., -600.),
+            (
+                Side::Right,
+                field.width - PADDLE_WIDTH,
+                field.width - BALL_SIZE / 2.,
+                600.,
+            ),
+        ] {
+            let mut world = world_with_ball(ball_x, dx);
+            let paddle = world.player_on(side).unwrap();
+            world
+                .render_components
+                .iter_mut()
+                .find(|renderable| renderable.id == paddle)
+                .unwrap()
+                .x = paddle_x;
+
+            let mut collision_system = CollisionSystem::new();
+            collision_system.resolve(&mut world, NOMINAL_DT);
+            assert_eq!(ball(&world).x, ball_x, "{:?} paddle bounced the ball", side);
+            let scorer = collision_system.ball_out_of_bounds(&mut world);
+            assert!(scorer.is_some() && scorer != Some(side), "{:?}", side);
+        }
+    }
+
+    fn renderable(x: f32, y: f32, width: f32, height: f32) -> Renderable {
+        Renderable {
+            id: EntityId(0),
+            kind: EntityKind::Ball,
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn paddle() -> Renderable {
+        Renderable {
+            kind: EntityKind::Paddle,
+            ..renderable(20., 100., PADDLE_WIDTH, PADDLE_HEIGHT)
+        }
+    }
+
+    /// Whether a ball now at `(x, y)` collides with [`paddle`], having not
+    /// moved since the last step.
+    fn resting_ball_hits(x: f32, y: f32) -> bool {
+        let ball = renderable(x, y, BALL_SIZE, BALL_SIZE);
+        let collision_system = CollisionSystem::new();
+        collision_system.overlapping(&paddle(), &ball)
+            || collision_system.crossed_face(&paddle(), &ball, (x, y))
+    }
+
+    #[test]
+    fn overlapping_or_touching_balls_collide() {
+        assert!(resting_ball_hits(30., 140.));
+        // Flush against the paddle's face, top and bottom.
+        assert!(resting_ball_hits(40., 140.));
+        assert!(resting_ball_hits(0., 140.));
+        assert!(resting_ball_hits(30., 80.));
+        assert!(resting_ball_hits(30., 200.));
+    }
+
+    #[test]
+    fn separated_balls_miss() {
+        assert!(!resting_ball_hits(40.01, 140.));
+        assert!(!resting_ball_hits(-0.01, 140.));
+        assert!(!resting_ball_hits(30., 79.99));
+        assert!(!resting_ball_hits(30., 200.01));
+    }
+
+    #[test]
+    fn corners_collide_only_when_they_overlap() {
+        // The paddle spans x 20..40 and y 100..200.
+        assert!(resting_ball_hits(39., 81.));
+        assert!(resting_ball_hits(1., 199.));
+        assert!(!resting_ball_hits(40.5, 80.5));
+        assert!(!resting_ball_hits(39., 200.5));
+    }
+
+    #[test]
+    fn a_ball_faster_than_the_paddle_is_wide_still_hits_it() {
+        let collision_system = CollisionSystem::new();
+        // Travelling 100 units left, clean through the paddle's right face.
+        let ball = renderable(-40., 140., BALL_SIZE, BALL_SIZE);
+        assert!(collision_system.crossed_face(&paddle(), &ball, (60., 140.)));
+        // The same step, crossing the face below the paddle, misses.
+        let ball = renderable(-40., 300., BALL_SIZE, BALL_SIZE);
+        assert!(!collision_system.crossed_face(&paddle(), &ball, (60., 300.)));
+        // Diagonally, the face is crossed at y = 150 although neither end of
+        // the step is level with the paddle.
+        let ball = renderable(-40., 250., BALL_SIZE, BALL_SIZE);
+        assert!(collision_system.crossed_face(&paddle(), &ball, (60., 50.)));
+    }
+
+    #[test]
+    fn bounces_leave_the_ball_just_outside_the_paddle() {
+        let mut collision_system = CollisionSystem::new();
+        let speed = |dx, dy| Speed {
+            id: EntityId(0),
+            dx,
+            dy,
+        };
+
+        // Off the right face, level with the paddle's center: no english.
+        let mut ball = renderable(35., 140., BALL_SIZE, BALL_SIZE);
+        let mut velocity = speed(-600., 0.);
+        collision_system.bounce(&paddle(), &mut ball, &mut velocity, 45.);
+        assert_eq!((ball.x, velocity.dx, velocity.dy), (40., 600., 0.));
+
+        // Off the left face, at the paddle's very top.
+        let mut ball = renderable(5., 80., BALL_SIZE, BALL_SIZE);
+        let mut velocity = speed(600., 0.);
+        collision_system.bounce(&paddle(), &mut ball, &mut velocity, -5.);
+        assert_eq!(ball.x, 0.);
+        assert_eq!(velocity.dx, -600.);
+        assert!(velocity.dy < 0., "{}", velocity.dy);
+
+        // English never makes the ball faster vertically than horizontally.
+        let mut ball = renderable(35., 199., BALL_SIZE, BALL_SIZE);
+        let mut velocity = speed(-600., 550.);
+        collision_system.bounce(&paddle(), &mut ball, &mut velocity, 45.);
+        assert_eq!((velocity.dx, velocity.dy), (600., 600.));
+    }
+
+    #[test]
+    fn a_fast_ball_bounces_back_from_the_paddle_it_passed() {
+        let mut world = world_with_ball(650., 6000.);
+        let paddle = world.player_on(Side::Right).unwrap();
+        let paddle = world
+            .render_components
+            .iter_mut()
+            .find(|renderable| renderable.id == paddle)
+            .unwrap();
+        // Still the right player's, but moved in so that one 100 unit step
+        // carries the ball from in front of it to behind it.
+        paddle.x = 600.;
+        paddle.y = 250.;
+
+        CollisionSystem::new().resolve(&mut world, NOMINAL_DT);
+        assert_eq!(ball(&world).x, 600. - BALL_SIZE);
+        assert_eq!(world.speed_components.last().unwrap().dx, -6000.);
+    }
+
+    #[test]
+    fn a_serve_that_crosses_the_paddle_and_the_goal_line_in_one_step_bounces() {
+        // 100 units a tick, with the right paddle where it spawned and level
+        // with the ball: the fourth step carries the ball's leading edge from
+        // in front of the paddle's face to past the goal line.
+        let field = Field::default();
+        let mut world = world_with_ball(field.width / 2., 6000.);
+        let right = world.player_on(Side::Right).unwrap();
+        let paddle = world
+            .render_components
+            .iter_mut()
+            .find(|renderable| renderable.id == right)
+            .unwrap();
+        let paddle_x = paddle.x;
+        assert_eq!(paddle_x, field.width - PADDLE_MARGIN - PADDLE_WIDTH);
+        paddle.y = 300. + BALL_SIZE / 2. - PADDLE_HEIGHT / 2.;
+
+        let control_system = ControlSystem::new(0.1, 2., PADDLE_SPEED_UPS);
+        let mut collision_system = CollisionSystem::new();
+        for tick in 0..4 {
+            control_system.step(&mut world, NOMINAL_DT);
+            collision_system.resolve(&mut world, NOMINAL_DT);
+            let scorer = collision_system.ball_out_of_bounds(&mut world);
+            assert_eq!(scorer, None, "scored at tick {}", tick);
+        }
+        assert_eq!(ball(&world).x, paddle_x - BALL_SIZE);
+        assert_eq!(world.speed_components.last().unwrap().dx, -6000.);
+    }
+
+    /// A field of another size than the default, still tall enough for
+    /// [`world_with_ball_on`]'s ball.
+    const WIDE: Field = Field {
+        width: 1000.,
+        height: 400.,
+    };
+
+    #[test]
+    fn goal_lines_follow_the_field_width() {
+        let mut world = world_with_ball_on(WIDE, WIDE.width - BALL_SIZE, 0.);
+        assert_eq!(CollisionSystem::new().ball_out_of_bounds(&mut world), None);
+
+        let mut world = world_with_ball_on(WIDE, WIDE.width - BALL_SIZE + 0.01, 0.);
+        assert_eq!(
+            CollisionSystem::new().ball_out_of_bounds(&mut world),
+            Some(Side::Left)
+        );
+        // The new ball is served from the center of this field.
+        assert_eq!((ball(&world).x, ball(&world).y), (500., 200.));
+    }
+
+    #[test]
+    fn paddles_and_walls_follow_the_field_size() {
+        // The right paddle spawns 40 units in from this field's goal line and
+        // turns back a ball that reaches it.
+        let mut world = world_with_ball_on(WIDE, WIDE.width - 40. - BALL_SIZE + 5., 600.);
+        let right = world.player_on(Side::Right).unwrap();
+        let paddle = world
+            .render_components
+            .iter()
+            .find(|renderable| renderable.id == right)
+            .unwrap();
+        assert_eq!(paddle.x, WIDE.width - 40.);
+        world.render_components.last_mut().unwrap().y = 140.;
+        CollisionSystem::new().resolve(&mut world, NOMINAL_DT);
+        assert_eq!(ball(&world).x, WIDE.width - 40. - BALL_SIZE);
+        assert_eq!(world.speed_components.last().unwrap().dx, -600.);
+
+        // The bottom wall is at this field's height.
+        let mut world = world_with_ball_on(WIDE, 500., 0.);
+        world.render_components.last_mut().unwrap().y = WIDE.height - BALL_SIZE + 5.;
+        world.speed_components.last_mut().unwrap().dy = 300.;
+        CollisionSystem::new().resolve(&mut world, NOMINAL_DT);
+        assert_eq!(ball(&world).y, WIDE.height - BALL_SIZE - 5.);
+        assert_eq!(world.speed_components.last().unwrap().dy, -300.);
+    }
+}
@@ -0,0 +1,534 @@
+//! Server settings, read from `PONG_*` environment variables and then from
+//! command-line flags, so a flag overrides the matching variable.
+
+use crate::physics::{
+    Field, DEFAULT_SERVE_SPEED_UPS, MIN_FIELD_HEIGHT, MIN_FIELD_WIDTH, PADDLE_MARGIN, PADDLE_WIDTH,
+    TICK_RATE_HZ,
+};
+use std::{fmt, net::SocketAddr, time::Duration};
+
+const DEFAULT_BIND: &str = "0.0.0.0:8080";
+const DEFAULT_WINNING_SCORE: u32 = 11;
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_LOBBY_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_SEND_RATE: f32 = 20.;
+const DEFAULT_BOT_REACTION_MS: u64 = 150;
+const DEFAULT_AXIS_DEAD_ZONE: f32 = 0.1;
+const DEFAULT_AXIS_CURVE: f32 = 2.;
+const DEFAULT_MAX_ROOMS: u32 = 50;
+
+/// Largest dead zone accepted; past this a stick barely moves the paddle
+/// before it is fully deflected.
+const MAX_AXIS_DEAD_ZONE: f32 = 0.9;
+/// Range of response curve exponents accepted: 1 is linear, larger values
+/// give finer control near the center.
+const MIN_AXIS_CURVE: f32 = 0.2;
+const MAX_AXIS_CURVE: f32 = 5.;
+
+/// Slowest reaction the bot may be given. A default serve crosses the field
+/// faster than this, so a slower bot would be no opponent at all.
+const MAX_BOT_REACTION_MS: u64 = 1000;
+
+/// Largest field either side may be. Positions are sent with two decimals,
+/// which stays exact enough well below this.
+const MAX_FIELD_SIZE: f32 = 10_000.;
+
+/// Fastest serve accepted: one that moves no further in a nominal tick than
+/// the gap between a paddle's face and its goal line, so a ball can't pass a
+/// paddle and score in the same step. That's 2400 u/s, whatever the field.
+const MAX_BALL_SPEED_UPS: f32 = (PADDLE_MARGIN + PADDLE_WIDTH) * TICK_RATE_HZ;
+
+pub struct Config {
+    pub bind: SocketAddr,
+    pub field: Field,
+    /// Speed of a fresh serve, in field units per second.
+    pub ball_speed_ups: f32,
+    pub winning_score: u32,
+    /// How long a player may stay silent before being evicted.
+    pub player_timeout: Duration,
+    /// How long a lone player may wait for an opponent before the room is
+    /// recycled.
+    pub lobby_timeout: Duration,
+    /// Whether a waiting player who moves their paddle restarts the lobby
+    /// timeout.
+    pub lobby_reset_on_activity: bool,
+    /// State broadcasts per second. Never faster than the simulation ticks.
+    pub send_rate: f32,
+    /// How long a lone player waits before the bot joins them, if it ever
+    /// does unasked.
+    pub bot_wait: Option<Duration>,
+    /// How often the bot looks at the ball; the longer, the easier it is to
+    /// beat.
+    pub bot_reaction: Duration,
+    /// Axis inputs at or below this magnitude leave the paddle still.
+    pub axis_dead_zone: f32,
+    /// Exponent of the response curve applied to axis inputs past the dead
+    /// zone.
+    pub axis_curve: f32,
+    /// Most matches open at once; a join that would need another is refused.
+    pub max_rooms: u32,
+    /// Whether clients of the unversioned legacy protocol may still join.
+    /// Deprecated; this will default to off in a later release.
+    pub legacy_clients: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: DEFAULT_BIND.parse().unwrap(),
+            field: Field::default(),
+            ball_speed_ups: DEFAULT_SERVE_SPEED_UPS,
+            winning_score: DEFAULT_WINNING_SCORE,
+            player_timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            lobby_timeout: Duration::from_secs(DEFAULT_LOBBY_TIMEOUT_SECS),
+            lobby_reset_on_activity: true,
+            send_rate: DEFAULT_SEND_RATE,
+            bot_wait: None,
+            bot_reaction: Duration::from_millis(DEFAULT_BOT_REACTION_MS),
+            axis_dead_zone: DEFAULT_AXIS_DEAD_ZONE,
+            axis_curve: DEFAULT_AXIS_CURVE,
+            max_rooms: DEFAULT_MAX_ROOMS,
+            legacy_clients: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// `--help` was given; not a failure, but there is nothing to run.
+    Help,
+    UnknownArgument(String),
+    MissingValue(&'static str),
+    Invalid {
+        name: &'static str,
+        value: String,
+        expected: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "help requested"),
+            ConfigError::UnknownArgument(arg) => write!(f, "unknown argument {}", arg),
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::Invalid {
+                name,
+                value,
+                expected,
+            } => write!(f, "invalid {} {:?}: expected {}", name, value, expected),
+        }
+    }
+}
+
+/// A setting that can be given as `--flag value`, `--flag=value` or through
+/// the environment variable `env`.
+struct Setting {
+    flag: &'static str,
+    env: &'static str,
+    value: &'static str,
+    help: &'static str,
+}
+
+const SETTINGS: [Setting; 15] = [
+    Setting {
+        flag: "--bind",
+        env: "PONG_BIND",
+        value: "ADDR",
+        help: "Address and port to listen on",
+    },
+    Setting {
+        flag: "--width",
+        env: "PONG_WIDTH",
+        value: "UNITS",
+        help: "Field width",
+    },
+    Setting {
+        flag: "--height",
+        env: "PONG_HEIGHT",
+        value: "UNITS",
+        help: "Field height",
+    },
+    Setting {
+        flag: "--ball-speed",
+        env: "PONG_BALL_SPEED",
+        value: "UPS",
+        help: "Serve speed, in field units per second",
+    },
+    Setting {
+        flag: "--winning-score",
+        env: "PONG_WINNING_SCORE",
+        value: "POINTS",
+        help: "Points needed to win a match",
+    },
+    Setting {
+        flag: "--timeout-secs",
+        env: "PONG_TIMEOUT_SECS",
+        value: "SECS",
+        help: "Evict players that send nothing for this long",
+    },
+    Setting {
+        flag: "--lobby-timeout-secs",
+        env: "PONG_LOBBY_TIMEOUT_SECS",
+        value: "SECS",
+        help: "Recycle the room of a player who waits this long for an opponent",
+    },
+    Setting {
+        flag: "--lobby-reset-on-activity",
+        env: "PONG_LOBBY_RESET_ON_ACTIVITY",
+        value: "BOOL",
+        help: "Restart the lobby timeout whenever the waiting player moves",
+    },
+    Setting {
+        flag: "--send-rate",
+        env: "PONG_SEND_RATE",
+        value: "HZ",
+        help: "State broadcasts per second",
+    },
+    Setting {
+        flag: "--bot-wait-secs",
+        env: "PONG_BOT_WAIT_SECS",
+        value: "SECS",
+        help: "Let the bot join a player who waits this long; 0 never does",
+    },
+    Setting {
+        flag: "--bot-reaction-ms",
+        env: "PONG_BOT_REACTION_MS",
+        value: "MS",
+        help: "How often the bot looks at the ball",
+    },
+    Setting {
+        flag: "--axis-dead-zone",
+        env: "PONG_AXIS_DEAD_ZONE",
+        value: "FRACTION",
+        help: "Ignore axis inputs up to this magnitude",
+    },
+    Setting {
+        flag: "--axis-curve",
+        env: "PONG_AXIS_CURVE",
+        value: "EXPONENT",
+        help: "Response curve for axis inputs; 1 is linear",
+    },
+    Setting {
+        flag: "--max-rooms",
+        env: "PONG_MAX_ROOMS",
+        value: "ROOMS",
+        help: "Refuse joins that would need a room beyond this many",
+    },
+    Setting {
+        flag: "--legacy-clients",
+        env: "PONG_LEGACY_CLIENTS",
+        value: "BOOL",
+        help: "Let clients of the unversioned protocol join (deprecated)",
+    },
+];
+
+pub fn usage() -> String {
+    let defaults = Config::default();
+    let mut usage = String::from("Usage: pong-server [OPTIONS]\n\nOptions:\n");
+    for setting in SETTINGS.iter() {
+        let default = match setting.flag {
+            "--bind" => defaults.bind.to_string(),
+            "--width" => format!("{:.0}", defaults.field.width),
+            "--height" => format!("{:.0}", defaults.field.height),
+            "--ball-speed" => format!("{:.0}", defaults.ball_speed_ups),
+            "--winning-score" => defaults.winning_score.to_string(),
+            "--timeout-secs" => defaults.player_timeout.as_secs().to_string(),
+            "--lobby-timeout-secs" => defaults.lobby_timeout.as_secs().to_string(),
+            "--lobby-reset-on-activity" => defaults.lobby_reset_on_activity.to_string(),
+            "--send-rate" => format!("{:.0}", defaults.send_rate),
+            "--bot-wait-secs" => defaults
+                .bot_wait
+                .map_or(0, |wait| wait.as_secs())
+                .to_string(),
+            "--bot-reaction-ms" => defaults.bot_reaction.as_millis().to_string(),
+            "--axis-dead-zone" => defaults.axis_dead_zone.to_string(),
+            "--axis-curve" => defaults.axis_curve.to_string(),
+            "--max-rooms" => defaults.max_rooms.to_string(),
+            "--legacy-clients" => defaults.legacy_clients.to_string(),
+            _ => unreachable!("every setting in SETTINGS has a default"),
+        };
+        usage.push_str(&format!(
+            "  {} <{}>\n          {} [env: {}] [default: {}]\n",
+            setting.flag, setting.value, setting.help, setting.env, default
+        ));
+    }
+    usage.push_str("  -h, --help\n          Print this message\n");
+    usage
+}
+
+fn invalid(setting: &Setting, value: &str, expected: String) -> ConfigError {
+    ConfigError::Invalid {
+        name: setting.flag,
+        value: value.to_string(),
+        expected,
+    }
+}
+
+fn parse_positive_secs(setting: &Setting, value: &str) -> Result<Duration, ConfigError> {
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| invalid(setting, value, "a positive whole number".into()))
+}
+
+/// Parses a finite number in `min..=max`.
+fn parse_in_range(setting: &Setting, value: &str, min: f32, max: f32) -> Result<f32, ConfigError> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .ok_or_else(|| invalid(setting, value, format!("a number from {} to {}", min, max)))
+}
+
+impl Config {
+    /// Builds the config from `env` first and then `args`, which should not
+    /// include the program name.
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        for setting in SETTINGS.iter() {
+            if let Some(value) = env(setting.env) {
+                config.set(setting, &value)?;
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(ConfigError::Help);
+            }
+
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let setting = SETTINGS
+                .iter()
+                .find(|setting| setting.flag == flag)
+                .ok_or_else(|| ConfigError::UnknownArgument(arg.clone()))?;
+            let value = match inline_value {
+                Some(value) => value.to_string(),
+                None => args.next().ok_or(ConfigError::MissingValue(setting.flag))?,
+            };
+            config.set(setting, &value)?;
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, setting: &Setting, value: &str) -> Result<(), ConfigError> {
+        match setting.flag {
+            "--bind" => {
+                self.bind = value
+                    .parse()
+                    .map_err(|_| invalid(setting, value, "an address like 0.0.0.0:8080".into()))?;
+            }
+            "--width" => {
+                self.field.width = parse_in_range(setting, value, MIN_FIELD_WIDTH, MAX_FIELD_SIZE)?;
+            }
+            "--height" => {
+                self.field.height =
+                    parse_in_range(setting, value, MIN_FIELD_HEIGHT, MAX_FIELD_SIZE)?;
+            }
+            "--ball-speed" => {
+                self.ball_speed_ups = parse_in_range(setting, value, 1., MAX_BALL_SPEED_UPS)?;
+            }
+            "--winning-score" => {
+                self.winning_score = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&points| points > 0)
+                    .ok_or_else(|| invalid(setting, value, "a positive whole number".into()))?;
+            }
+            "--timeout-secs" => {
+                self.player_timeout = parse_positive_secs(setting, value)?;
+            }
+            "--lobby-timeout-secs" => {
+                self.lobby_timeout = parse_positive_secs(setting, value)?;
+            }
+            "--lobby-reset-on-activity" => {
+                self.lobby_reset_on_activity = value
+                    .parse()
+                    .map_err(|_| invalid(setting, value, "true or false".into()))?;
+            }
+            "--send-rate" => {
+                self.send_rate = parse_in_range(setting, value, 1., TICK_RATE_HZ)?;
+            }
+            "--bot-wait-secs" => {
+                let secs = value
+                    .parse::<u64>()
+                    .map_err(|_| invalid(setting, value, "a whole number".into()))?;
+                self.bot_wait = (secs > 0).then(|| Duration::from_secs(secs));
+            }
+            "--bot-reaction-ms" => {
+                let ms = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&ms| ms <= MAX_BOT_REACTION_MS)
+                    .ok_or_else(|| {
+                        let expected = format!("a whole number up to {}", MAX_BOT_REACTION_MS);
+                        invalid(setting, value, expected)
+                    })?;
+                self.bot_reaction = Duration::from_millis(ms);
+            }
+            "--axis-dead-zone" => {
+                self.axis_dead_zone = parse_in_range(setting, value, 0., MAX_AXIS_DEAD_ZONE)?;
+            }
+            "--axis-curve" => {
+                self.axis_curve = parse_in_range(setting, value, MIN_AXIS_CURVE, MAX_AXIS_CURVE)?;
+            }
+            "--max-rooms" => {
+                self.max_rooms = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&rooms| rooms > 0)
+                    .ok_or_else(|| invalid(setting, value, "a positive whole number".into()))?;
+            }
+            "--legacy-clients" => {
+                self.legacy_clients = value
+                    .parse()
+                    .map_err(|_| invalid(setting, value, "true or false".into()))?;
+            }
+            _ => unreachable!("every setting in SETTINGS has a match arm"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let args = args.iter().map(|arg| arg.to_string());
+        Config::parse(args, |key| {
+            env.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn nothing_given_is_the_default() {
+        let config = parse(&[], &[]).unwrap();
+        assert_eq!(config.bind, DEFAULT_BIND.parse().unwrap());
+        assert_eq!(config.field, Field::default());
+        assert_eq!(config.winning_score, DEFAULT_WINNING_SCORE);
+    }
+
+    #[test]
+    fn flags_take_separate_or_inline_values() {
+        let config = parse(&["--bind", "127.0.0.1:9000", "--width=1000"], &[]).unwrap();
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(config.field.width, 1000.);
+
+        let config = parse(
+            &[
+                "--height=400",
+                "--winning-score",
+                "3",
+                "--bot-wait-secs=0",
+                "--ball-speed=2400",
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.field.height, 400.);
+        assert_eq!(config.ball_speed_ups, 2400.);
+        assert_eq!(config.winning_score, 3);
+        assert_eq!(config.bot_wait, None);
+    }
+
+    #[test]
+    fn flags_override_the_environment() {
+        let env = [("PONG_WIDTH", "1000"), ("PONG_TIMEOUT_SECS", "30")];
+        let config = parse(&["--width", "1200"], &env).unwrap();
+        assert_eq!(config.field.width, 1200.);
+        assert_eq!(config.player_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn out_of_range_values_are_invalid() {
+        for (flag, value) in [
+            ("--width", "10"),
+            ("--height", "100000"),
+            ("--ball-speed", "0"),
+            ("--ball-speed", "2401"),
+            ("--winning-score", "0"),
+            ("--timeout-secs", "0"),
+            ("--lobby-timeout-secs", "-1"),
+            ("--lobby-reset-on-activity", "yes"),
+            ("--send-rate", "1000"),
+            ("--bot-reaction-ms", "5000"),
+            ("--axis-dead-zone", "0.95"),
+            ("--axis-curve", "NaN"),
+            ("--max-rooms", "0"),
+            ("--legacy-clients", "on"),
+            ("--bind", "localhost"),
+        ] {
+            assert!(
+                matches!(
+                    parse(&[flag, value], &[]),
+                    Err(ConfigError::Invalid { name, .. }) if name == flag
+                ),
+                "{} {}",
+                flag,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_environment_values_are_reported() {
+        assert!(matches!(
+            parse(&[], &[("PONG_WIDTH", "wide")]),
+            Err(ConfigError::Invalid {
+                name: "--width",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_and_incomplete_arguments_are_rejected() {
+        assert_eq!(
+            parse(&["--colour", "red"], &[]).err(),
+            Some(ConfigError::UnknownArgument("--colour".to_string()))
+        );
+        assert_eq!(
+            parse(&["8080"], &[]).err(),
+            Some(ConfigError::UnknownArgument("8080".to_string()))
+        );
+        assert_eq!(
+            parse(&["--width"], &[]).err(),
+            Some(ConfigError::MissingValue("--width"))
+        );
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        for flag in ["-h", "--help"] {
+            assert_eq!(
+                parse(&["--width", "1000", flag, "--bogus"], &[]).err(),
+                Some(ConfigError::Help)
+            );
+        }
+    }
+
+    #[test]
+    fn usage_lists_every_setting_with_its_default() {
+        let usage = usage();
+        for setting in SETTINGS.iter() {
+            assert!(usage.contains(setting.flag), "{}", setting.flag);
+            assert!(usage.contains(setting.env), "{}", setting.env);
+        }
+        assert!(usage.contains("[default: 2]"), "{}", usage);
+        assert!(usage.contains("[default: 0.1]"), "{}", usage);
+    }
+}
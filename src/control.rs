@@ -0,0 +1,328 @@
+//! Paddle input from clients and the per-tick movement of every entity.
+
+use crate::{
+    physics::{self, Field, MAX_DT, NOMINAL_DT},
+    world::{Renderable, World},
+};
+use std::{io, net::SocketAddr};
+
+/// How far a client-reported position may stray from where the server puts
+/// the paddle before the server sends a correction.
+const CORRECTION_EPSILON: f32 = 0.5;
+
+pub struct ControlSystem {
+    dead_zone: f32,
+    response_exponent: f32,
+    max_paddle_speed: f32,
+}
+
+impl ControlSystem {
+    pub fn new(dead_zone: f32, response_exponent: f32, max_paddle_speed: f32) -> Self {
+        ControlSystem {
+            dead_zone,
+            response_exponent,
+            max_paddle_speed,
+        }
+    }
+
+    /// Axis inputs at or below this magnitude leave the paddle still.
+    pub fn dead_zone(&self) -> f32 {
+        self.dead_zone
+    }
+
+    /// Exponent of the response curve applied past the dead zone.
+    pub fn curve(&self) -> f32 {
+        self.response_exponent
+    }
+
+    /// Maps a raw analog axis in [-1, 1] onto [-1, 1], swallowing anything
+    /// inside the dead zone and rescaling the remainder through the response
+    /// curve so full deflection still reaches exactly 1.
+    fn normalize_axis(&self, axis: f32) -> f32 {
+        let magnitude = axis.abs().min(1.);
+        if magnitude <= self.dead_zone {
+            return 0.;
+        }
+
+        let scaled = (magnitude - self.dead_zone) / (1. - self.dead_zone);
+        scaled.powf(self.response_exponent).copysign(axis)
+    }
+
+    pub fn apply_axis(
+        &self,
+        axis: f32,
+        source: SocketAddr,
+        world: &mut World,
+    ) -> Result<(), io::Error> {
+        if !axis.is_finite() {
+            return Err(io::Error::other("Axis value must be finite"));
+        }
+
+        let dy = self.normalize_axis(axis) * self.max_paddle_speed;
+        let id = world.player_entity(source);
+        if let Some(speed) = world
+            .speed_components
+            .iter_mut()
+            .find(|speed| Some(speed.id) == id)
+        {
+            speed.dy = dy;
+        }
+        Ok(())
+    }
+
+    /// Applies an absolute position update sent `dt` seconds after the
+    /// player's previous position update, and sets the paddle's velocity from how far
+    /// it moved. Paddles only move along y, stay inside the field and move
+    /// no faster than `max_paddle_speed`, so x is ignored and y is limited;
+    /// if that changed the reported position noticeably, the corrected
+    /// position is returned so the client can be told.
+    pub fn update_players(
+        &self,
+        x: f32,
+        y: f32,
+        dt: f32,
+        source: SocketAddr,
+        world: &mut World,
+    ) -> Option<(f32, f32)> {
+        let id = world.player_entity(source)?;
+        let field = world.field;
+        let player = world
+            .render_components
+            .iter_mut()
+            .find(|player| player.id == id)?;
+        let speed = world
+            .speed_components
+            .iter_mut()
+            .find(|speed| speed.id == id)?;
+
+        // Since the previous update the paddle has been stepped along at the
+        // velocity that update gave it; measure from where it left the paddle
+        // so the extrapolation isn't counted against the client.
+        let previous_y =
+            (player.y - speed.dy * dt.min(MAX_DT)).clamp(0., field.height - player.height);
+        // Allow at least a nominal frame of movement so packets that arrive
+        // bunched together aren't corrected for jitter.
+        let dt = dt.clamp(NOMINAL_DT, MAX_DT);
+        let max_step = self.max_paddle_speed * dt;
+        player.y = previous_y + (y - previous_y).clamp(-max_step, max_step);
+        self.clamp_to_field(player, &field);
+        speed.dy = (player.y - previous_y) / dt;
+        let corrected = (player.x, player.y);
+
+        let strayed = (x - corrected.0).abs() > CORRECTION_EPSILON
+            || (y - corrected.1).abs() > CORRECTION_EPSILON;
+        strayed.then_some(corrected)
+    }
+
+    /// Keeps every part of a paddle between the walls and in front of both
+    /// goal lines, so the ball can never meet the back of a paddle behind
+    /// the line.
+    fn clamp_to_field(&self, paddle: &mut Renderable, field: &Field) {
+        paddle.x = paddle.x.clamp(0., field.width - paddle.width);
+        paddle.y = paddle.y.clamp(0., field.height - paddle.height);
+    }
+
+    /// Advances every paddle and the ball by one tick of `dt` seconds.
+    pub fn step(&self, world: &mut World, dt: f32) {
+        self.update_paddles(world, dt);
+        self.update_ball(world, dt);
+    }
+
+    fn update_ball(&self, world: &mut World, dt: f32) {
+        if world.render_components.len() >= 2 {
+            if let Some((renderable, speed)) = world
+                .render_components
+                .iter_mut()
+                .zip(world.speed_components.iter_mut())
+                .find(|(renderable, speed)| renderable.is_ball() && renderable.id == speed.id)
+            {
+                renderable.x = physics::integrate(renderable.x, speed.dx, dt);
+                renderable.y = physics::integrate(renderable.y, speed.dy, dt);
+            }
+        }
+    }
+
+    fn update_paddles(&self, world: &mut World, dt: f32) {
+        let field = world.field;
+        world
+            .render_components
+            .iter_mut()
+            .zip(world.speed_components.iter())
+            .filter(|(r, s)| !r.is_ball() && r.id == s.id)
+            .for_each(|(r, s)| {
+                r.y = physics::integrate(r.y, s.dy, dt);
+                self.clamp_to_field(r, &field);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{PADDLE_HEIGHT, PADDLE_MARGIN, PADDLE_SPEED_UPS, PADDLE_WIDTH};
+    use std::time::Instant;
+
+    fn source() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 1000))
+    }
+
+    /// A world whose only entity is a left paddle at `y`, moving at `dy`.
+    fn world_with_paddle(y: f32, dy: f32) -> World {
+        let mut world = World::new(Field::default(), 1200.);
+        world.create_player(source(), None, None, Instant::now());
+        world.render_components[0].y = y;
+        world.speed_components[0].dy = dy;
+        world
+    }
+
+    fn control_system() -> ControlSystem {
+        ControlSystem::new(0.1, 2., PADDLE_SPEED_UPS)
+    }
+
+    fn update(world: &mut World, y: f32, dt: f32) -> Option<(f32, f32)> {
+        control_system().update_players(PADDLE_MARGIN, y, dt, source(), world)
+    }
+
+    fn paddle(world: &World) -> (f32, f32) {
+        (world.render_components[0].y, world.speed_components[0].dy)
+    }
+
+    #[test]
+    fn updates_set_the_velocity_they_imply() {
+        let mut world = world_with_paddle(100., 0.);
+        assert_eq!(update(&mut world, 120., 0.1), None);
+        assert_eq!(paddle(&world), (120., 200.));
+
+        let mut world = world_with_paddle(100., 0.);
+        assert_eq!(update(&mut world, 90., 0.25), None);
+        assert_eq!(paddle(&world), (90., -40.));
+    }
+
+    #[test]
+    fn updates_measure_from_before_the_extrapolation() {
+        // The last update left the paddle moving at 200, and the ticks since
+        // carried it from 100 to 120.
+        let mut world = world_with_paddle(120., 200.);
+        assert_eq!(update(&mut world, 130., 0.1), None);
+        assert_eq!(paddle(&world), (130., 300.));
+    }
+
+    #[test]
+    fn updates_faster_than_a_paddle_moves_are_corrected() {
+        let mut world = world_with_paddle(100., 0.);
+        assert_eq!(update(&mut world, 300., 0.1), Some((PADDLE_MARGIN, 140.)));
+        assert_eq!(paddle(&world), (140., PADDLE_SPEED_UPS));
+    }
+
+    #[test]
+    fn bunched_updates_may_still_move_a_nominal_frame() {
+        let mut world = world_with_paddle(100., 0.);
+        let frame = PADDLE_SPEED_UPS * NOMINAL_DT;
+        assert_eq!(update(&mut world, 100. + frame, 0.), None);
+        let (y, dy) = paddle(&world);
+        assert_eq!(y, 100. + frame);
+        assert!((dy - PADDLE_SPEED_UPS).abs() < 0.01, "{}", dy);
+    }
+
+    #[test]
+    fn updates_stop_at_the_top_wall() {
+        let mut world = world_with_paddle(10., 0.);
+        assert_eq!(update(&mut world, -50., 0.25), Some((PADDLE_MARGIN, 0.)));
+        assert_eq!(paddle(&world), (0., -40.));
+    }
+
+    #[test]
+    fn updates_stop_at_the_bottom_wall() {
+        let bottom = Field::default().height - PADDLE_HEIGHT;
+        let mut world = world_with_paddle(bottom - 10., 0.);
+        assert_eq!(
+            update(&mut world, bottom + 60., 0.25),
+            Some((PADDLE_MARGIN, bottom))
+        );
+        assert_eq!(paddle(&world), (bottom, 40.));
+    }
+
+    #[test]
+    fn updates_cannot_move_a_paddle_sideways() {
+        let mut world = world_with_paddle(100., 0.);
+        let corrected =
+            control_system().update_players(300., 100., NOMINAL_DT, source(), &mut world);
+        assert_eq!(corrected, Some((PADDLE_MARGIN, 100.)));
+        assert_eq!(world.render_components[0].x, PADDLE_MARGIN);
+    }
+
+    #[test]
+    fn updates_cannot_move_the_right_paddle_sideways() {
+        let mut world = world_with_paddle(100., 0.);
+        let right = SocketAddr::from(([127, 0, 0, 1], 1001));
+        world.create_player(right, None, None, Instant::now());
+        let lock_x = Field::default().width - PADDLE_MARGIN - PADDLE_WIDTH;
+        assert_eq!(world.render_components[1].x, lock_x);
+
+        for x in [lock_x - 60., lock_x + 30., PADDLE_MARGIN] {
+            let corrected = control_system().update_players(x, 100., NOMINAL_DT, right, &mut world);
+            assert_eq!(corrected, Some((lock_x, 100.)), "{}", x);
+            assert_eq!(world.render_components[1].x, lock_x);
+        }
+        // Drift within the correction epsilon goes unanswered.
+        let corrected =
+            control_system().update_players(lock_x + 0.25, 100., NOMINAL_DT, right, &mut world);
+        assert_eq!(corrected, None);
+        assert_eq!(world.render_components[1].x, lock_x);
+    }
+
+    #[test]
+    fn updates_from_strangers_are_ignored() {
+        let mut world = world_with_paddle(100., 0.);
+        let stranger = SocketAddr::from(([127, 0, 0, 1], 2000));
+        let corrected = control_system().update_players(20., 300., 0.1, stranger, &mut world);
+        assert_eq!(corrected, None);
+        assert_eq!(paddle(&world), (100., 0.));
+    }
+
+    #[test]
+    fn the_dead_zone_swallows_small_axis_values() {
+        let control_system = control_system();
+        for axis in [0., -0., 0.05, -0.05, 0.1, -0.1] {
+            assert_eq!(control_system.normalize_axis(axis), 0., "{}", axis);
+        }
+        assert!(control_system.normalize_axis(0.11) > 0.);
+        assert!(control_system.normalize_axis(-0.11) < 0.);
+    }
+
+    #[test]
+    fn the_response_curve_is_monotonic() {
+        for (dead_zone, curve) in [(0.1, 2.), (0., 1.), (0.3, 0.5), (0.9, 5.)] {
+            let control_system = ControlSystem::new(dead_zone, curve, PADDLE_SPEED_UPS);
+            let mut previous = control_system.normalize_axis(-1.);
+            for step in -999..=1000 {
+                let axis = step as f32 / 1000.;
+                let normalized = control_system.normalize_axis(axis);
+                assert!(
+                    normalized >= previous,
+                    "{} fell to {} at {} with {:?}",
+                    previous,
+                    normalized,
+                    axis,
+                    (dead_zone, curve)
+                );
+                previous = normalized;
+            }
+        }
+    }
+
+    #[test]
+    fn full_deflection_reaches_exactly_max_speed() {
+        let control_system = control_system();
+        let mut world = world_with_paddle(100., 0.);
+        for (axis, dy) in [(1., PADDLE_SPEED_UPS), (-1., -PADDLE_SPEED_UPS)] {
+            control_system
+                .apply_axis(axis, source(), &mut world)
+                .unwrap();
+            assert_eq!(world.speed_components[0].dy, dy);
+        }
+        // Past full deflection is still full deflection.
+        control_system.apply_axis(3., source(), &mut world).unwrap();
+        assert_eq!(world.speed_components[0].dy, PADDLE_SPEED_UPS);
+    }
+}
@@ -0,0 +1,428 @@
+//! Cross-checks that should hold for the world at the end of every tick,
+//! and for the server's bookkeeping across rooms at the end of every update.
+//!
+//! Each check is a named closure over `&World` or over [`Bookkeeping`]. A
+//! failing check panics in debug builds so it surfaces immediately; in
+//! release builds it bumps a counter and logs when the check starts failing,
+//! since a stuck violation would otherwise be logged every tick.
+
+use crate::{
+    bot::BotSystem,
+    lobby::LobbySystem,
+    log::Logger,
+    rooms::Rooms,
+    world::{Owner, World},
+};
+
+/// What the server keeps about its rooms outside their worlds.
+pub struct Bookkeeping<'a> {
+    pub rooms: &'a Rooms,
+    pub lobby_system: &'a LobbySystem,
+    pub bot_system: &'a BotSystem,
+}
+
+struct InvariantCheck<F: ?Sized> {
+    name: &'static str,
+    holds: Box<F>,
+    failing: bool,
+}
+
+type WorldCheck = InvariantCheck<dyn Fn(&World) -> bool + Send>;
+type BookkeepingCheck = InvariantCheck<dyn Fn(&Bookkeeping<'_>) -> bool + Send>;
+
+impl<F: ?Sized> InvariantCheck<F> {
+    /// Records whether the check `held` this time, counting and logging a
+    /// violation as the module describes.
+    fn record(&mut self, held: bool, violations: &mut u64, logger: &Logger) {
+        if held {
+            self.failing = false;
+            return;
+        }
+
+        if cfg!(debug_assertions) {
+            panic!("Invariant violated: {}", self.name);
+        }
+
+        *violations += 1;
+        if !self.failing {
+            logger.log(format_args!(
+                "Invariant violated: {} (violations so far: {})",
+                self.name, violations
+            ));
+        }
+        self.failing = true;
+    }
+}
+
+pub struct InvariantAuditor {
+    checks: Vec<WorldCheck>,
+    bookkeeping_checks: Vec<BookkeepingCheck>,
+    violations: u64,
+    logger: Logger,
+}
+
+fn is_ball(world: &World, index: usize) -> bool {
+    world.render_components[index].is_ball()
+}
+
+impl InvariantAuditor {
+    pub fn new() -> Self {
+        let mut auditor = InvariantAuditor {
+            checks: Vec::new(),
+            bookkeeping_checks: Vec::new(),
+            violations: 0,
+            logger: Logger::stdout(),
+        };
+
+        auditor.check("render and speed components pair up", |world| {
+            world.render_components.len() == world.speed_components.len()
+                && world
+                    .render_components
+                    .iter()
+                    .zip(world.speed_components.iter())
+                    .all(|(r, s)| r.id == s.id)
+        });
+        auditor.check("at most two players", |world| {
+            (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .count()
+                <= 2
+        });
+        auditor.check("at most one ball", |world| {
+            (0..world.render_components.len())
+                .filter(|&i| is_ball(world, i))
+                .count()
+                <= 1
+        });
+        auditor.check("ball only in play with two players", |world| {
+            let balls = (0..world.render_components.len())
+                .filter(|&i| is_ball(world, i))
+                .count();
+            balls == 0 || world.render_components.len() == 3
+        });
+        auditor.check("positions are finite", |world| {
+            world
+                .render_components
+                .iter()
+                .all(|r| r.x.is_finite() && r.y.is_finite())
+        });
+        auditor.check("sizes are positive", |world| {
+            world
+                .render_components
+                .iter()
+                .all(|r| r.width > 0. && r.height > 0.)
+        });
+        auditor.check("velocities are finite", |world| {
+            world
+                .speed_components
+                .iter()
+                .all(|s| s.dx.is_finite() && s.dy.is_finite())
+        });
+        auditor.check("paddles stay in front of the goal lines", |world| {
+            (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .map(|i| &world.render_components[i])
+                .all(|paddle| paddle.x >= 0. && paddle.x + paddle.width <= world.field.width)
+        });
+        auditor.check("paddles stay between the walls", |world| {
+            (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .map(|i| &world.render_components[i])
+                .all(|paddle| paddle.y >= 0. && paddle.y + paddle.height <= world.field.height)
+        });
+        auditor.check("paddles have no horizontal velocity", |world| {
+            (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .all(|i| world.speed_components[i].dx == 0.)
+        });
+        auditor.check("entity ids are unique and allocated", |world| {
+            world.render_components.iter().enumerate().all(|(i, r)| {
+                r.id.0 < world.next_entity_id
+                    && world.render_components[..i]
+                        .iter()
+                        .all(|other| other.id != r.id)
+            })
+        });
+        auditor.check("despawned entities are gone", |world| {
+            world.despawned.iter().all(|despawned| {
+                despawned.id.0 < world.next_entity_id
+                    && world.render_components.iter().all(|r| r.id != despawned.id)
+            })
+        });
+        auditor.check("every player has exactly one appearance", |world| {
+            let mut players = (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .map(|i| world.render_components[i].id);
+            let appearances_for = |id| {
+                world
+                    .appearance_components
+                    .iter()
+                    .filter(|appearance| appearance.id == id)
+                    .count()
+            };
+            players.all(|id| appearances_for(id) == 1)
+                && world
+                    .appearance_components
+                    .iter()
+                    .all(|appearance| appearances_for(appearance.id) == 1)
+                && world.appearance_components.iter().all(|appearance| {
+                    world
+                        .render_components
+                        .iter()
+                        .any(|r| r.id == appearance.id)
+                })
+        });
+        auditor.check("every player has exactly one score", |world| {
+            let players = (0..world.render_components.len())
+                .filter(|&i| !is_ball(world, i))
+                .count();
+            world.score_components.len() == players
+                && world.score_components.iter().all(|score| {
+                    matches!(world.owner(score.id), Some(Owner::Player(_) | Owner::Ai))
+                        && world
+                            .score_components
+                            .iter()
+                            .filter(|other| other.id == score.id)
+                            .count()
+                            == 1
+                })
+        });
+        auditor.check("every player has exactly one last-seen time", |world| {
+            world.last_seen_components.len() == world.player_addresses().count()
+                && world.last_seen_components.iter().all(|last_seen| {
+                    world.player_address(last_seen.id).is_some()
+                        && world
+                            .last_seen_components
+                            .iter()
+                            .filter(|other| other.id == last_seen.id)
+                            .count()
+                            == 1
+                })
+        });
+        auditor.check("every entity has exactly one owner", |world| {
+            world.ownership_components.len() == world.render_components.len()
+                && world.render_components.iter().all(|r| {
+                    world
+                        .ownership_components
+                        .iter()
+                        .filter(|ownership| ownership.id == r.id)
+                        .count()
+                        == 1
+                })
+        });
+        auditor.check("the server owns the ball and nothing else", |world| {
+            (0..world.render_components.len()).all(|i| {
+                let id = world.render_components[i].id;
+                let server_owned = world.owner(id) == Some(Owner::Server);
+                server_owned == is_ball(world, i)
+            })
+        });
+        auditor.check("each peer owns at most one entity", |world| {
+            world.ownership_components.iter().all(|a| {
+                a.owner == Owner::Server
+                    || world
+                        .ownership_components
+                        .iter()
+                        .filter(|b| b.owner == a.owner)
+                        .count()
+                        == 1
+            })
+        });
+        auditor.check("scores match the goals of this match", |world| {
+            world.score_components.iter().all(|score| {
+                world.goals.iter().filter(|&&id| id == score.id).count() == score.points as usize
+            })
+        });
+        auditor.check("player colors are distinct", |world| {
+            world
+                .appearance_components
+                .iter()
+                .enumerate()
+                .all(|(i, a)| {
+                    world.appearance_components[..i]
+                        .iter()
+                        .all(|b| b.color != a.color)
+                })
+        });
+
+        auditor.check_bookkeeping("routes lead to the peer's paddle", |server| {
+            server.rooms.routes().all(|(source, id)| {
+                server
+                    .rooms
+                    .get(id)
+                    .is_some_and(|world| world.player_entity(source).is_some())
+            })
+        });
+        auditor.check_bookkeeping("every player is routed to their room", |server| {
+            server.rooms.iter().all(|(id, world)| {
+                world
+                    .player_addresses()
+                    .all(|source| server.rooms.routes().any(|route| route == (source, id)))
+            })
+        });
+        auditor.check_bookkeeping("the lobby only times open rooms", |server| {
+            server
+                .lobby_system
+                .rooms()
+                .all(|id| server.rooms.get(id).is_some())
+        });
+        auditor.check_bookkeeping("the bot only tracks open rooms", |server| {
+            server
+                .bot_system
+                .rooms()
+                .all(|id| server.rooms.get(id).is_some())
+        });
+
+        auditor
+    }
+
+    fn check(&mut self, name: &'static str, holds: impl Fn(&World) -> bool + Send + 'static) {
+        self.checks.push(InvariantCheck {
+            name,
+            holds: Box::new(holds),
+            failing: false,
+        });
+    }
+
+    fn check_bookkeeping(
+        &mut self,
+        name: &'static str,
+        holds: impl Fn(&Bookkeeping<'_>) -> bool + Send + 'static,
+    ) {
+        self.bookkeeping_checks.push(InvariantCheck {
+            name,
+            holds: Box::new(holds),
+            failing: false,
+        });
+    }
+
+    pub fn violations(&self) -> u64 {
+        self.violations
+    }
+
+    /// Sends this auditor's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    pub fn audit(&mut self, world: &World) {
+        for check in self.checks.iter_mut() {
+            let held = (check.holds)(world);
+            check.record(held, &mut self.violations, &self.logger);
+        }
+    }
+
+    /// Checks what the server keeps across rooms. Only meaningful once
+    /// closed rooms have been torn down, at the end of an update.
+    pub fn audit_bookkeeping(&mut self, server: &Bookkeeping) {
+        for check in self.bookkeeping_checks.iter_mut() {
+            let held = (check.holds)(server);
+            check.record(held, &mut self.violations, &self.logger);
+        }
+    }
+}
+
+impl Default for InvariantAuditor {
+    fn default() -> Self {
+        InvariantAuditor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::Field;
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    fn world_in_play() -> World {
+        let mut world = World::new(Field::default(), 1200.);
+        for port in [1000, 1001] {
+            let source = SocketAddr::from(([127, 0, 0, 1], port));
+            world.create_player(source, None, None, Instant::now());
+        }
+        world.create_ball();
+        world
+    }
+
+    #[test]
+    fn a_sound_world_passes() {
+        let mut auditor = InvariantAuditor::new();
+        auditor.audit(&world_in_play());
+        auditor.audit(&World::new(Field::default(), 1200.));
+        assert_eq!(auditor.violations, 0);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "Invariant violated: positions are finite")
+    )]
+    fn a_broken_world_trips_a_check() {
+        let mut world = world_in_play();
+        world.render_components[2].x = f32::NAN;
+        let mut auditor = InvariantAuditor::new();
+        auditor.audit(&world);
+        // Release builds count the violation instead, once per audit.
+        auditor.audit(&world);
+        assert_eq!(auditor.violations, 2);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "Invariant violated: scores match the goals of this match")
+    )]
+    fn a_point_without_a_goal_trips_a_check() {
+        let mut world = world_in_play();
+        world.score_components[0].points += 1;
+        let mut auditor = InvariantAuditor::new();
+        auditor.audit(&world);
+        assert_eq!(auditor.violations, 1);
+    }
+
+    #[test]
+    fn a_player_leaving_starts_the_goal_history_over() {
+        let mut world = world_in_play();
+        world.score_components[0].points += 1;
+        world.goals.push(world.score_components[0].id);
+        let mut auditor = InvariantAuditor::new();
+        auditor.audit(&world);
+
+        world.remove_player(SocketAddr::from(([127, 0, 0, 1], 1001)));
+        auditor.audit(&world);
+        assert_eq!(auditor.violations, 0);
+    }
+
+    fn audit_bookkeeping(rooms: &Rooms) -> u64 {
+        let mut auditor = InvariantAuditor::new();
+        auditor.audit_bookkeeping(&Bookkeeping {
+            rooms,
+            lobby_system: &LobbySystem::new(Duration::from_secs(1), true),
+            bot_system: &BotSystem::new(None, Duration::ZERO, 1.),
+        });
+        auditor.violations
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "Invariant violated: routes lead to the peer's paddle")
+    )]
+    fn a_route_to_a_room_without_the_player_trips_a_check() {
+        let mut rooms = Rooms::new(Field::default(), 1200., 1);
+        let source = SocketAddr::from(([127, 0, 0, 1], 1000));
+        let room = rooms.open_room().unwrap();
+        rooms
+            .get_mut(room)
+            .unwrap()
+            .create_player(source, None, None, Instant::now());
+        rooms.assign(source, room);
+        assert_eq!(audit_bookkeeping(&rooms), 0);
+
+        rooms.get_mut(room).unwrap().remove_player(source);
+        assert_eq!(audit_bookkeeping(&rooms), 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! The unversioned protocol the server spoke before [`protocol`] version 1,
+//! kept for clients that can't be updated yet.
+//!
+//! A legacy client sends a bare `join`, then its paddle as `x y w h` after
+//! every move. The server answers the join with the paddle it got, and each
+//! move with every other entity as `x y w h`, all written with Rust's default
+//! float formatting. A legacy client understands nothing else, so it is never
+//! sent anything else.
+//!
+//! Everything here is frozen: the golden tests pin the exact bytes old
+//! clients depend on, so don't change them to make a refactor pass. Support
+//! is deprecated, on by default for now behind `--legacy-clients`, and will
+//! be off by default in a later release.
+//!
+//! [`protocol`]: crate::protocol
+
+use crate::protocol::EntityState;
+use std::{fmt, str};
+
+/// A datagram from a legacy client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    Join,
+    /// The client's paddle position. Legacy clients also send the paddle's
+    /// size, which the server ignores.
+    Update {
+        x: f32,
+        y: f32,
+    },
+}
+
+/// Parses a legacy datagram: exactly `join`, or at least four finite
+/// floats, of which anything past the fourth is ignored as it always was.
+pub fn parse(bytes: &[u8]) -> Option<Request> {
+    let text = str::from_utf8(bytes).ok()?;
+    if text == "join" {
+        return Some(Request::Join);
+    }
+
+    let mut floats = text.split_whitespace().map(|part| part.parse::<f32>());
+    let mut next = || floats.next()?.ok().filter(|float| float.is_finite());
+    let (x, y) = (next()?, next()?);
+    let (_width, _height) = (next()?, next()?);
+    Some(Request::Update { x, y })
+}
+
+/// The reply to a legacy join: the paddle the client got, as `x y w h`.
+pub struct JoinReply<'a>(pub &'a EntityState);
+
+impl fmt::Display for JoinReply<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let paddle = self.0;
+        write!(
+            f,
+            "{} {} {} {}",
+            paddle.x, paddle.y, paddle.width, paddle.height
+        )
+    }
+}
+
+/// The reply to a legacy move: every entity but the client's own paddle, as
+/// `x y w h` each, separated by spaces.
+pub struct State<'a>(pub &'a [EntityState]);
+
+impl fmt::Display for State<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, entity) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "{} {} {} {}",
+                entity.x, entity.y, entity.width, entity.height
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::EntityKind;
+
+    fn entity(kind: EntityKind, x: f32, y: f32, size: (f32, f32)) -> EntityState {
+        EntityState {
+            id: 0,
+            kind,
+            x,
+            y,
+            width: size.0,
+            height: size.1,
+            dx: 0.,
+            dy: 0.,
+        }
+    }
+
+    #[test]
+    fn join_replies_are_golden() {
+        let left = entity(EntityKind::Paddle, 20., 100., (20., 100.));
+        let right = entity(EntityKind::Paddle, 760., 100., (20., 100.));
+        assert_eq!(JoinReply(&left).to_string().as_bytes(), b"20 100 20 100");
+        assert_eq!(JoinReply(&right).to_string().as_bytes(), b"760 100 20 100");
+    }
+
+    #[test]
+    fn states_are_golden() {
+        let paddle = entity(EntityKind::Paddle, 760., 133.33333, (20., 100.));
+        let ball = entity(EntityKind::Ball, 412.5, -0.25, (20., 20.));
+        assert_eq!(
+            State(&[paddle, ball]).to_string().as_bytes(),
+            b"760 133.33333 20 100 412.5 -0.25 20 20"
+        );
+        assert_eq!(
+            State(&[paddle]).to_string().as_bytes(),
+            b"760 133.33333 20 100"
+        );
+        assert_eq!(State(&[]).to_string().as_bytes(), b"");
+    }
+
+    #[test]
+    fn requests_parse_as_they_always_did() {
+        assert_eq!(parse(b"join"), Some(Request::Join));
+        assert_eq!(
+            parse(b"20 150.5 20 100"),
+            Some(Request::Update { x: 20., y: 150.5 })
+        );
+        assert_eq!(
+            parse(b"760 0 20 100 extra"),
+            Some(Request::Update { x: 760., y: 0. })
+        );
+        for rejected in [
+            &b"join "[..],
+            b"1 join",
+            b"20 100 20",
+            b"20 NaN 20 100",
+            b"20 inf 20 100",
+            b"x 100 20 100",
+            b"\xff",
+            b"",
+        ] {
+            assert_eq!(parse(rejected), None, "{:?}", rejected);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! A multiplayer Pong server over a text protocol on UDP.
+//!
+//! [`server::Server`] runs the game; the binary only parses its
+//! [`config::Config`] and drives it over a UDP socket. Anything else that
+//! implements [`network::Transport`], such as [`network::MemoryTransport`],
+//! can drive it instead, and a host that embeds the server can route its
+//! log lines through a [`log::Logger`] of its own.
+
+pub mod bot;
+pub mod collision;
+pub mod config;
+pub mod control;
+pub mod invariants;
+pub mod legacy;
+pub mod lobby;
+pub mod log;
+pub mod network;
+pub mod physics;
+pub mod protocol;
+pub mod rooms;
+pub mod score;
+pub mod server;
+pub mod world;
+
+/// Server version, git commit and build profile, e.g. `0.1.0+1a2b3c4.release`.
+pub const BUILD_INFO: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "+",
+    env!("PONG_GIT_HASH"),
+    ".",
+    env!("PONG_BUILD_PROFILE"),
+);
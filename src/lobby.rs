@@ -0,0 +1,278 @@
+//! Players who are waiting too long for an opponent, or who have gone quiet.
+
+use crate::{
+    log::Logger,
+    network::{NetworkSystem, Transport},
+    rooms::RoomId,
+    world::World,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Recycles a room when a lone player has been waiting for an opponent for
+/// longer than `timeout`.
+pub struct LobbySystem {
+    timeout: Duration,
+    reset_on_activity: bool,
+    waiting_since: HashMap<RoomId, Instant>,
+    logger: Logger,
+}
+
+impl LobbySystem {
+    pub fn new(timeout: Duration, reset_on_activity: bool) -> Self {
+        LobbySystem {
+            timeout,
+            reset_on_activity,
+            waiting_since: HashMap::new(),
+            logger: Logger::stdout(),
+        }
+    }
+
+    /// Sends this system's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// Restarts the wait in `room` if the packet from `source`, already
+    /// applied to `world`, left the sender's paddle moving. Updates that
+    /// repeat the paddle's position, or an axis inside the dead zone, are
+    /// no sign that anyone is there.
+    pub fn activity(&mut self, room: RoomId, source: SocketAddr, world: &World, now: Instant) {
+        let id = world.player_entity(source);
+        let moving = world
+            .speed_components
+            .iter()
+            .any(|speed| Some(speed.id) == id && speed.dy != 0.);
+
+        if self.reset_on_activity && moving {
+            if let Some(since) = self.waiting_since.get_mut(&room) {
+                *since = now;
+            }
+        }
+    }
+
+    /// Drops the wait in `room`, once the room has closed.
+    pub fn forget(&mut self, room: RoomId) {
+        self.waiting_since.remove(&room);
+    }
+
+    /// Every room this system is timing a wait in.
+    pub fn rooms(&self) -> impl Iterator<Item = RoomId> + '_ {
+        self.waiting_since.keys().copied()
+    }
+
+    pub fn check<T: Transport>(
+        &mut self,
+        room: RoomId,
+        world: &mut World,
+        network_system: &mut NetworkSystem<T>,
+        now: Instant,
+    ) -> io::Result<()> {
+        if world.render_components.len() != 1 {
+            self.waiting_since.remove(&room);
+            return Ok(());
+        }
+
+        let since = *self.waiting_since.entry(room).or_insert(now);
+        if now.duration_since(since) < self.timeout {
+            return Ok(());
+        }
+
+        let sent = network_system.send_lobby_timeout(world);
+        self.logger.log(format_args!(
+            "Lobby of room {} timed out, recycling world",
+            room
+        ));
+        world.reset();
+        self.waiting_since.remove(&room);
+        sent
+    }
+}
+
+/// Evicts players whose peer has sent nothing for longer than `timeout`,
+/// e.g. because the client crashed or was closed without leaving.
+pub struct TimeoutSystem {
+    timeout: Duration,
+}
+
+impl TimeoutSystem {
+    pub fn new(timeout: Duration) -> Self {
+        TimeoutSystem { timeout }
+    }
+
+    /// Evicts every silent player, even if telling an opponent fails, and
+    /// returns the first failure.
+    pub fn check<T: Transport>(
+        &self,
+        world: &mut World,
+        network_system: &mut NetworkSystem<T>,
+        now: Instant,
+    ) -> io::Result<()> {
+        let mut sent = Ok(());
+        while let Some(source) = world
+            .last_seen_components
+            .iter()
+            .find(|last_seen| now.duration_since(last_seen.at) > self.timeout)
+            .and_then(|last_seen| world.player_address(last_seen.id))
+        {
+            sent = sent.and(network_system.remove_player(source, world, "timed out"));
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::{MemoryPeer, MemoryTransport},
+        physics::Field,
+        protocol::Message,
+        rooms::Rooms,
+    };
+
+    fn source() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 1000))
+    }
+
+    /// A room with one player in it, who joined at `start` and has been
+    /// waiting for an opponent since, with a lobby timeout of 120 s.
+    struct Lobby {
+        lobby_system: LobbySystem,
+        rooms: Rooms,
+        room: RoomId,
+        network_system: NetworkSystem<MemoryTransport>,
+        peer: MemoryPeer,
+        start: Instant,
+    }
+
+    impl Lobby {
+        fn new(reset_on_activity: bool) -> Self {
+            let start = Instant::now();
+            let mut rooms = Rooms::new(Field::default(), 1200., 1);
+            let room = rooms.open_room().unwrap();
+            rooms
+                .get_mut(room)
+                .unwrap()
+                .create_player(source(), None, None, start);
+            rooms.assign(source(), room);
+            let (transport, peer) = MemoryTransport::new();
+            let mut lobby = Lobby {
+                lobby_system: LobbySystem::new(Duration::from_secs(120), reset_on_activity),
+                rooms,
+                room,
+                network_system: NetworkSystem::new(transport, start),
+                peer,
+                start,
+            };
+            lobby.check(0);
+            lobby
+        }
+
+        fn at(&self, secs: u64) -> Instant {
+            self.start + Duration::from_secs(secs)
+        }
+
+        fn world(&mut self) -> &mut World {
+            self.rooms.get_mut(self.room).unwrap()
+        }
+
+        /// Checks the lobby `secs` after the start and returns whether it
+        /// timed out.
+        fn check(&mut self, secs: u64) -> bool {
+            let now = self.at(secs);
+            let world = self.rooms.get_mut(self.room).unwrap();
+            self.lobby_system
+                .check(self.room, world, &mut self.network_system, now)
+                .unwrap();
+            let sent: Vec<(SocketAddr, Vec<u8>)> = self.peer.from_server.try_iter().collect();
+            let timed_out = sent
+                .iter()
+                .any(|(_, bytes)| Message::deserialize(bytes) == Ok(Message::LobbyTimeout));
+            if timed_out {
+                assert_eq!(sent.len(), 1);
+                assert_eq!(sent[0].0, source());
+            }
+            timed_out
+        }
+
+        /// A packet from `from`, `secs` after the start, that left the
+        /// player's paddle moving at `dy`.
+        fn activity(&mut self, secs: u64, from: SocketAddr, dy: f32) {
+            let now = self.at(secs);
+            self.world().speed_components[0].dy = dy;
+            let world = self.rooms.get_mut(self.room).unwrap();
+            self.lobby_system.activity(self.room, from, world, now);
+        }
+    }
+
+    #[test]
+    fn waiting_too_long_recycles_the_room() {
+        let mut lobby = Lobby::new(true);
+        assert!(!lobby.check(119));
+        assert_eq!(lobby.world().render_components.len(), 1);
+
+        assert!(lobby.check(120));
+        assert!(lobby.world().render_components.is_empty());
+        assert!(lobby.world().ownership_components.is_empty());
+
+        // The room closes through the usual teardown.
+        let mut closed = Vec::new();
+        let lobby_system = &mut lobby.lobby_system;
+        lobby.rooms.tear_down(|room| {
+            lobby_system.forget(room);
+            closed.push(room);
+        });
+        assert_eq!(closed, [lobby.room]);
+        assert_eq!(lobby.rooms.iter().count(), 0);
+        assert_eq!(lobby.rooms.room_of(source()), None);
+        assert!(lobby.lobby_system.waiting_since.is_empty());
+    }
+
+    #[test]
+    fn moving_restarts_the_wait() {
+        let mut lobby = Lobby::new(true);
+        lobby.activity(60, source(), 200.);
+        assert!(!lobby.check(179));
+        assert!(lobby.check(180));
+    }
+
+    #[test]
+    fn standing_still_does_not_restart_the_wait() {
+        let mut lobby = Lobby::new(true);
+        lobby.activity(60, source(), 0.);
+        assert!(lobby.check(120));
+    }
+
+    #[test]
+    fn strangers_and_disabled_resets_do_not_restart_the_wait() {
+        let mut lobby = Lobby::new(false);
+        lobby.activity(60, source(), 200.);
+        assert!(lobby.check(120));
+
+        let mut lobby = Lobby::new(true);
+        let stranger = SocketAddr::from(([127, 0, 0, 1], 2000));
+        lobby.activity(60, stranger, 200.);
+        assert!(lobby.check(120));
+    }
+
+    #[test]
+    fn an_opponent_ends_the_wait() {
+        let mut lobby = Lobby::new(true);
+        let opponent = SocketAddr::from(([127, 0, 0, 1], 2000));
+        let now = lobby.at(60);
+        lobby.world().create_player(opponent, None, None, now);
+        assert!(!lobby.check(60));
+        assert!(!lobby.check(300));
+
+        // Once the opponent leaves, the wait starts over.
+        lobby.world().remove_player(opponent);
+        assert!(!lobby.check(300));
+        assert!(!lobby.check(419));
+        assert!(lobby.check(420));
+    }
+}
@@ -0,0 +1,36 @@
+//! Where the server's log lines go. The binary prints them; a host that
+//! embeds a [`Server`] can hand it a [`Logger`] of its own instead.
+//!
+//! [`Server`]: crate::server::Server
+
+use std::{fmt, sync::Arc};
+
+/// A cheaply cloned handle to a log sink, shared by every system of a server.
+#[derive(Clone)]
+pub struct Logger {
+    sink: Arc<dyn Fn(fmt::Arguments) + Send + Sync>,
+}
+
+impl Logger {
+    /// A logger that passes every line to `sink`.
+    pub fn new(sink: impl Fn(fmt::Arguments) + Send + Sync + 'static) -> Self {
+        Logger {
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// A logger that prints every line to stdout.
+    pub fn stdout() -> Self {
+        Logger::new(|line| println!("{}", line))
+    }
+
+    pub fn log(&self, line: fmt::Arguments) {
+        (self.sink)(line)
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger::stdout()
+    }
+}
@@ -0,0 +1,632 @@
+//! Sending and receiving [`Message`]s over a [`Transport`].
+
+use crate::{
+    control::ControlSystem,
+    legacy,
+    log::Logger,
+    protocol::{
+        EntityState, Message, PeerTraffic, ProtocolError, MAX_MESSAGE_SIZE, MAX_REPORTED_SCORES,
+    },
+    rooms::{RoomId, Rooms},
+    world::{Owner, Side, World},
+    BUILD_INFO,
+};
+use std::{
+    collections::HashSet,
+    fmt::{self, Write},
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    time::Instant,
+};
+
+/// A datagram socket, or anything that behaves like a nonblocking one:
+/// `recv_from` fails with [`io::ErrorKind::WouldBlock`] when nothing is
+/// waiting.
+pub trait Transport {
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn send_to(&mut self, buf: &[u8], destination: SocketAddr) -> io::Result<usize>;
+}
+
+impl Transport for UdpSocket {
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn send_to(&mut self, buf: &[u8], destination: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, destination)
+    }
+}
+
+/// A datagram and the peer it came from or goes to.
+pub type Datagram = (SocketAddr, Vec<u8>);
+
+/// A [`Transport`] backed by channels instead of a socket, so a server can be
+/// driven in-process, e.g. by a load generator. Datagrams longer than the
+/// receive buffer are truncated, like UDP does.
+pub struct MemoryTransport {
+    incoming: Receiver<Datagram>,
+    outgoing: Sender<Datagram>,
+}
+
+/// The far end of a [`MemoryTransport`]: what is sent on `to_server`
+/// arrives at the server tagged with the given source address, and
+/// everything the server sends shows up on `from_server`.
+pub struct MemoryPeer {
+    pub to_server: Sender<Datagram>,
+    pub from_server: Receiver<Datagram>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> (MemoryTransport, MemoryPeer) {
+        let (to_server, incoming) = mpsc::channel();
+        let (outgoing, from_server) = mpsc::channel();
+        let transport = MemoryTransport { incoming, outgoing };
+        let peer = MemoryPeer {
+            to_server,
+            from_server,
+        };
+        (transport, peer)
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.incoming.try_recv() {
+            Ok((source, datagram)) => {
+                let size = datagram.len().min(buf.len());
+                buf[..size].copy_from_slice(&datagram[..size]);
+                Ok((size, source))
+            }
+            Err(TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn send_to(&mut self, buf: &[u8], destination: SocketAddr) -> io::Result<usize> {
+        self.outgoing
+            .send((destination, buf.to_vec()))
+            .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(buf.len())
+    }
+}
+
+/// Coarse classification of a peer by source address, so local test traffic
+/// can be told apart from real players.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PeerClass {
+    Loopback,
+    Private,
+    Public,
+}
+
+const PEER_CLASSES: [PeerClass; 3] = [PeerClass::Loopback, PeerClass::Private, PeerClass::Public];
+
+impl PeerClass {
+    pub fn from_ip(ip: IpAddr) -> Self {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+
+        match ip {
+            IpAddr::V4(v4) if v4.is_loopback() => PeerClass::Loopback,
+            IpAddr::V4(v4) if v4.is_private() || v4.is_link_local() => PeerClass::Private,
+            IpAddr::V6(v6) if v6.is_loopback() => PeerClass::Loopback,
+            IpAddr::V6(v6) if v6.is_unique_local() || v6.is_unicast_link_local() => {
+                PeerClass::Private
+            }
+            _ => PeerClass::Public,
+        }
+    }
+}
+
+impl fmt::Display for PeerClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerClass::Loopback => write!(f, "loopback"),
+            PeerClass::Private => write!(f, "private"),
+            PeerClass::Public => write!(f, "public"),
+        }
+    }
+}
+
+pub struct NetworkSystem<T: Transport = UdpSocket> {
+    transport: T,
+    // One byte larger than any valid message so oversized datagrams, which
+    // recv_from truncates, can be told apart and rejected.
+    buf: [u8; MAX_MESSAGE_SIZE + 1],
+    // Scratch space reused by send_state so the steady-state tick doesn't
+    // allocate.
+    out_buf: String,
+    state_order: Vec<usize>,
+    state_entities: Vec<EntityState>,
+    /// Numbers state packets so clients can drop stale or duplicated ones.
+    state_sequence: u64,
+    /// The origin of server timestamps and uptime.
+    started: Instant,
+    packets_received: u64,
+    /// `packets_received`, split by [`PeerClass`] in declaration order.
+    packets_received_by_class: [u64; 3],
+    packets_sent: u64,
+    /// Peers speaking the unversioned [`legacy`] protocol, which get their
+    /// join reply and state in it and nothing else.
+    legacy_peers: HashSet<SocketAddr>,
+    logger: Logger,
+}
+
+impl NetworkSystem<UdpSocket> {
+    /// Binds a nonblocking UDP socket at `bind`, for a server started at
+    /// `started`.
+    pub fn bind(bind: SocketAddr, started: Instant) -> io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        Ok(NetworkSystem::new(socket, started))
+    }
+}
+
+impl<T: Transport> NetworkSystem<T> {
+    /// Server timestamps and uptime count from `started`.
+    pub fn new(transport: T, started: Instant) -> Self {
+        NetworkSystem {
+            transport,
+            buf: [0; MAX_MESSAGE_SIZE + 1],
+            out_buf: String::with_capacity(MAX_MESSAGE_SIZE),
+            state_order: Vec::with_capacity(8),
+            state_entities: Vec::with_capacity(8),
+            state_sequence: 0,
+            started,
+            packets_received: 0,
+            packets_received_by_class: [0; 3],
+            packets_sent: 0,
+            legacy_peers: HashSet::new(),
+            logger: Logger::stdout(),
+        }
+    }
+
+    /// Sends this system's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    pub fn receive(&mut self) -> io::Result<(usize, SocketAddr)> {
+        let received = self.transport.recv_from(&mut self.buf)?;
+        self.packets_received += 1;
+        self.packets_received_by_class[PeerClass::from_ip(received.1.ip()) as usize] += 1;
+        Ok(received)
+    }
+
+    /// Milliseconds from the server's start to `now`.
+    fn server_time_ms(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_millis() as u64
+    }
+
+    pub fn parse_request(&self, size: usize) -> Result<Message, ProtocolError> {
+        Message::deserialize(&self.buf[..size])
+    }
+
+    /// Parses the datagram of `size` bytes from `source` as a [`legacy`]
+    /// request, if it is one: anything from a legacy peer, or a bare legacy
+    /// join from anyone else.
+    pub fn parse_legacy_request(&self, size: usize, source: SocketAddr) -> Option<legacy::Request> {
+        let request = legacy::parse(&self.buf[..size]);
+        if self.legacy_peers.contains(&source) || request == Some(legacy::Request::Join) {
+            return request;
+        }
+        None
+    }
+
+    /// Starts speaking the legacy protocol to `peer`. Returns whether it is
+    /// new to it, so the caller can warn once per peer.
+    pub fn add_legacy_peer(&mut self, peer: SocketAddr) -> bool {
+        self.legacy_peers.insert(peer)
+    }
+
+    /// Forgets the legacy peers `playing` says are no longer in a match, so
+    /// the address can join again in either protocol.
+    pub fn forget_legacy_peers(&mut self, mut playing: impl FnMut(SocketAddr) -> bool) {
+        self.legacy_peers.retain(|&peer| playing(peer));
+    }
+
+    /// Sends `message`, unless `destination` is a legacy peer, which
+    /// wouldn't understand it.
+    pub fn send(&mut self, message: &Message, destination: SocketAddr) -> io::Result<()> {
+        if self.legacy_peers.contains(&destination) {
+            return Ok(());
+        }
+        self.send_datagram(message, destination)
+    }
+
+    fn send_datagram(
+        &mut self,
+        datagram: &dyn fmt::Display,
+        destination: SocketAddr,
+    ) -> io::Result<()> {
+        self.out_buf.clear();
+        write!(self.out_buf, "{}", datagram).map_err(|_| io::Error::other("Failed to format"))?;
+        if self.out_buf.len() > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                ProtocolError::TooLarge(self.out_buf.len()).to_string(),
+            ));
+        }
+
+        self.transport
+            .send_to(self.out_buf.as_bytes(), destination)?;
+        self.packets_sent += 1;
+        Ok(())
+    }
+
+    pub fn send_pong(
+        &mut self,
+        client_time: u64,
+        source: SocketAddr,
+        now: Instant,
+    ) -> io::Result<()> {
+        let message = Message::Pong {
+            client_time,
+            server_time_ms: self.server_time_ms(now),
+        };
+        self.send(&message, source)
+    }
+
+    pub fn send_stats(
+        &mut self,
+        rooms: &Rooms,
+        source: SocketAddr,
+        now: Instant,
+    ) -> io::Result<()> {
+        let message = self.stats(rooms, now);
+        self.send(&message, source)
+    }
+
+    /// A [`Message::StatsReport`] on `rooms` and this system's traffic.
+    pub fn stats(&self, rooms: &Rooms, now: Instant) -> Message {
+        let worlds = || rooms.iter().map(|(_, world)| world);
+        let mut by_class = [PeerTraffic::default(); 3];
+        for (class, traffic) in PEER_CLASSES.iter().zip(by_class.iter_mut()) {
+            traffic.players = worlds()
+                .flat_map(|world| world.player_addresses())
+                .filter(|address| PeerClass::from_ip(address.ip()) == *class)
+                .count() as u32;
+            traffic.packets_received = self.packets_received_by_class[*class as usize];
+        }
+        let [loopback, private, public] = by_class;
+        Message::StatsReport {
+            rooms: worlds().count() as u32,
+            max_rooms: rooms.max_rooms(),
+            players: worlds()
+                .map(|world| world.player_addresses().count())
+                .sum::<usize>() as u32,
+            balls_in_play: worlds()
+                .filter(|world| {
+                    world
+                        .render_components
+                        .iter()
+                        .any(|renderable| renderable.is_ball())
+                })
+                .count() as u32,
+            scores: worlds()
+                .map(|world| (world.points(Side::Left), world.points(Side::Right)))
+                .take(MAX_REPORTED_SCORES)
+                .collect(),
+            uptime_secs: now.saturating_duration_since(self.started).as_secs(),
+            packets_received: self.packets_received,
+            packets_sent: self.packets_sent,
+            loopback,
+            private,
+            public,
+            server: BUILD_INFO.to_string(),
+        }
+    }
+
+    pub fn send_error(&mut self, reason: &str, destination: SocketAddr) -> io::Result<()> {
+        let message = Message::Error {
+            reason: reason.to_string(),
+        };
+        self.send(&message, destination)
+    }
+
+    /// Gives the peer at `source` a paddle in `world`, if there is one free,
+    /// and tells it the rules `control_system` plays by.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_join(
+        &mut self,
+        color: Option<u32>,
+        avatar: Option<u8>,
+        source: SocketAddr,
+        room: RoomId,
+        world: &mut World,
+        control_system: &ControlSystem,
+        now: Instant,
+    ) -> io::Result<()> {
+        if world.player_entity(source).is_some() {
+            return Err(io::Error::other("Already joined"));
+        }
+
+        if world.player_addresses().count() < 2 {
+            let index = world.create_player(source, color, avatar, now);
+            let paddle = world.entity_state(index);
+            let appearance = world.appearance_components[world.appearance_components.len() - 1];
+            self.logger.log(format_args!(
+                "Player joined room {} from {} peer! Players in room: {}",
+                room,
+                PeerClass::from_ip(source.ip()),
+                world.player_addresses().count()
+            ));
+            if self.legacy_peers.contains(&source) {
+                self.send_datagram(&legacy::JoinReply(&paddle), source)?;
+            } else {
+                let response = Message::JoinAck {
+                    paddle,
+                    color: appearance.color,
+                    avatar: appearance.avatar,
+                    lock_x: paddle.x,
+                    dead_zone: control_system.dead_zone(),
+                    curve: control_system.curve(),
+                    room: room.0,
+                    server: BUILD_INFO.to_string(),
+                };
+                self.send(&response, source)?;
+            }
+            self.send_opponents(world)?;
+            self.send_score(world)
+        } else {
+            Err(io::Error::other("Game is full"))
+        }
+    }
+
+    pub fn handle_leave(&mut self, source: SocketAddr, world: &mut World) -> io::Result<()> {
+        if world.player_entity(source).is_none() {
+            return Err(io::Error::other("Not joined"));
+        }
+
+        self.remove_player(source, world, "left")
+    }
+
+    /// Takes the peer at `source` out of the game and tells the other player,
+    /// if there is one, that their opponent is gone. The player is removed
+    /// even if telling the other one fails.
+    pub fn remove_player(
+        &mut self,
+        source: SocketAddr,
+        world: &mut World,
+        reason: &str,
+    ) -> io::Result<()> {
+        if world.remove_player(source).is_none() {
+            return Ok(());
+        }
+
+        self.logger.log(format_args!(
+            "Player from {} peer {}! Players in room: {}",
+            PeerClass::from_ip(source.ip()),
+            reason,
+            world.player_addresses().count()
+        ));
+        let remaining: Vec<SocketAddr> = world.player_addresses().collect();
+        for address in remaining {
+            self.send(&Message::OpponentLeft, address)?;
+        }
+        Ok(())
+    }
+
+    /// Tells each player how their opponent chose to look.
+    pub fn send_opponents(&mut self, world: &World) -> io::Result<()> {
+        for recipient in world.appearance_components.iter() {
+            let address = match world.player_address(recipient.id) {
+                Some(address) => address,
+                None => continue,
+            };
+            for opponent in world
+                .appearance_components
+                .iter()
+                .filter(|opponent| opponent.id != recipient.id)
+            {
+                let message = Message::Opponent {
+                    color: opponent.color,
+                    avatar: opponent.avatar,
+                };
+                self.send(&message, address)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the players who were in `world` when each entity since the last
+    /// call was removed, and are still there, about it once.
+    pub fn send_despawns(&mut self, world: &mut World) -> io::Result<()> {
+        let mut sent = Ok(());
+        for despawned in world.despawned.iter() {
+            let message = Message::Despawn { id: despawned.id.0 };
+            for &address in despawned.recipients.iter() {
+                if world.player_entity(address).is_some() {
+                    sent = sent.and(self.send(&message, address));
+                }
+            }
+        }
+        world.despawned.clear();
+        sent
+    }
+
+    /// Sends the current score, left player first, to both players.
+    pub fn send_score(&mut self, world: &World) -> io::Result<()> {
+        let message = Message::Score {
+            left: world.points(Side::Left),
+            right: world.points(Side::Right),
+        };
+        for address in world.player_addresses() {
+            self.send(&message, address)?;
+        }
+        Ok(())
+    }
+
+    pub fn send_result(&mut self, world: &World, winner: Side) -> io::Result<()> {
+        let winner = world.player_on(winner);
+        for ownership in world.ownership_components.iter() {
+            if let Owner::Player(address) = ownership.owner {
+                let message = Message::GameOver {
+                    won: Some(ownership.id) == winner,
+                };
+                self.send(&message, address)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells a client where its paddle really is, after it reported a
+    /// position the server corrected.
+    pub fn send_correction(&mut self, x: f32, y: f32, source: SocketAddr) -> io::Result<()> {
+        self.send(&Message::Correction { x, y }, source)
+    }
+
+    pub fn send_lobby_timeout(&mut self, world: &World) -> io::Result<()> {
+        for address in world.player_addresses() {
+            self.send(&Message::LobbyTimeout, address)?;
+        }
+        Ok(())
+    }
+
+    /// Sends every player in `world` its view of the world.
+    pub fn broadcast_state(&mut self, world: &World, now: Instant) -> io::Result<()> {
+        for address in world.player_addresses() {
+            self.send_state(world, address, now)?;
+        }
+        Ok(())
+    }
+
+    pub fn send_state(
+        &mut self,
+        world: &World,
+        source: SocketAddr,
+        now: Instant,
+    ) -> io::Result<()> {
+        if self.legacy_peers.contains(&source) {
+            return Ok(());
+        }
+        let entities = match self.view(world, source) {
+            Some(entities) => entities,
+            None => return Ok(()),
+        };
+
+        self.state_sequence += 1;
+        let message = Message::State {
+            sequence: self.state_sequence,
+            tick: world.tick,
+            server_time_ms: self.server_time_ms(now),
+            entities,
+        };
+        let sent = self.send(&message, source);
+        if let Message::State { entities, .. } = message {
+            self.state_entities = entities;
+        }
+        sent
+    }
+
+    /// Answers a legacy peer's move with its view of the world.
+    pub fn send_legacy_state(&mut self, world: &World, source: SocketAddr) -> io::Result<()> {
+        let entities = match self.view(world, source) {
+            Some(entities) => entities,
+            None => return Ok(()),
+        };
+        let sent = self.send_datagram(&legacy::State(&entities), source);
+        self.state_entities = entities;
+        sent
+    }
+
+    /// Every entity in `world` but the paddle of the player at `source`, in
+    /// id order, or `None` if there is nothing to show them. The entities
+    /// are in the scratch Vec, lent out; give it back afterwards.
+    fn view(&mut self, world: &World, source: SocketAddr) -> Option<Vec<EntityState>> {
+        if world.render_components.len() < 2 {
+            return None;
+        }
+        let recipient = world.player_entity(source)?;
+
+        self.state_order.clear();
+        self.state_order.extend(
+            (0..world.render_components.len())
+                .filter(|&i| world.render_components[i].id != recipient),
+        );
+        self.state_order
+            .sort_unstable_by_key(|&i| world.render_components[i].id);
+
+        let mut entities = std::mem::take(&mut self.state_entities);
+        entities.clear();
+        entities.extend(self.state_order.iter().map(|&i| world.entity_state(i)));
+        Some(entities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(ip: &str) -> PeerClass {
+        PeerClass::from_ip(ip.parse().unwrap())
+    }
+
+    fn assert_classes(class_of: PeerClass, ips: &[&str]) {
+        for ip in ips {
+            assert_eq!(class(ip), class_of, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn loopback_peers() {
+        assert_classes(
+            PeerClass::Loopback,
+            &["127.0.0.1", "127.1.2.3", "::1", "::ffff:127.0.0.1"],
+        );
+    }
+
+    #[test]
+    fn private_and_link_local_peers() {
+        assert_classes(
+            PeerClass::Private,
+            &[
+                "10.0.0.1",
+                "172.16.0.1",
+                "172.31.255.255",
+                "192.168.1.10",
+                "169.254.1.1",
+                "fc00::1",
+                "fd12:3456::1",
+                "fe80::1",
+                "::ffff:10.1.2.3",
+                "::ffff:192.168.0.1",
+                "::ffff:169.254.0.1",
+            ],
+        );
+    }
+
+    #[test]
+    fn public_peers() {
+        assert_classes(
+            PeerClass::Public,
+            &[
+                "8.8.8.8",
+                "172.32.0.1",
+                "192.169.0.1",
+                "2606:4700::1111",
+                "fec0::1",
+                "::ffff:8.8.8.8",
+                "::ffff:172.15.0.1",
+            ],
+        );
+    }
+
+    #[test]
+    fn oversized_messages_are_an_error() {
+        let (transport, peer) = MemoryTransport::new();
+        let mut network_system = NetworkSystem::new(transport, Instant::now());
+        let destination = SocketAddr::from(([127, 0, 0, 1], 5000));
+        let message = Message::Error {
+            reason: "x".repeat(MAX_MESSAGE_SIZE),
+        };
+
+        let sent = network_system.send(&message, destination);
+        assert_eq!(sent.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(peer.from_server.try_iter().count(), 0);
+        assert_eq!(network_system.packets_sent, 0);
+    }
+}
@@ -0,0 +1,67 @@
+//! Canonical units for the simulation.
+//!
+//! Positions and sizes are in field units, with the origin at the top-left
+//! corner of the field. Velocities are in field units per second (suffixed
+//! `_UPS`) and are only ever integrated against a `dt` in seconds, through
+//! [`integrate`].
+
+pub const DEFAULT_FIELD_WIDTH: f32 = 800.;
+pub const DEFAULT_FIELD_HEIGHT: f32 = 600.;
+
+pub const PADDLE_WIDTH: f32 = 20.;
+pub const PADDLE_HEIGHT: f32 = 100.;
+/// Gap between a paddle and its own goal line at spawn.
+pub const PADDLE_MARGIN: f32 = 20.;
+pub const PADDLE_SPAWN_Y: f32 = 100.;
+pub const PADDLE_SPEED_UPS: f32 = 400.;
+
+pub const BALL_SIZE: f32 = 20.;
+/// How much dy a paddle hit adds, as a fraction of the ball's horizontal
+/// speed, when the ball meets the paddle's very end. A center hit adds none.
+pub const BOUNCE_ENGLISH: f32 = 0.75;
+
+/// Simulation ticks per second.
+pub const TICK_RATE_HZ: f32 = 60.;
+/// Length of one fixed simulation tick, which is also the frame length the
+/// original per-frame constants were tuned for.
+pub const NOMINAL_DT: f32 = 1. / TICK_RATE_HZ;
+/// The serve used to be `dx = 20` applied per frame; this is the same speed
+/// at the nominal frame rate.
+pub const DEFAULT_SERVE_SPEED_UPS: f32 = 20. / NOMINAL_DT;
+
+/// Smallest field that still fits both paddles with a ball's width of room
+/// between them.
+pub const MIN_FIELD_WIDTH: f32 = 2. * (PADDLE_MARGIN + PADDLE_WIDTH) + BALL_SIZE;
+/// Smallest field a paddle spawns fully inside of.
+pub const MIN_FIELD_HEIGHT: f32 = PADDLE_SPAWN_Y + PADDLE_HEIGHT;
+
+/// Size of the playing field. The walls are at `y = 0` and `y = height`,
+/// the goal lines at `x = 0` and `x = width`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Field {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Field {
+            width: DEFAULT_FIELD_WIDTH,
+            height: DEFAULT_FIELD_HEIGHT,
+        }
+    }
+}
+
+/// Longest step integration accepts. Anything longer is almost certainly a
+/// `dt` in the wrong unit (milliseconds, or frames) rather than a slow tick.
+pub const MAX_DT: f32 = 0.25;
+
+/// Advances `position` by `velocity_ups` over `dt` seconds.
+pub fn integrate(position: f32, velocity_ups: f32, dt: f32) -> f32 {
+    debug_assert!(
+        dt.is_finite() && (0. ..=MAX_DT).contains(&dt),
+        "dt must be in seconds, got {}",
+        dt
+    );
+    position + velocity_ups * dt
+}
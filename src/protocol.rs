@@ -0,0 +1,1124 @@
+//! Wire format shared by the server and its clients.
+//!
+//! Every datagram is UTF-8 text: the protocol version, a message tag, then
+//! the message's fields, separated by spaces, e.g. `1 update 20.00 150.00`.
+//! Floats are written with [`TextFloat`], except settings clients must
+//! match exactly, which are written in full.
+//!
+//! Version 1 replaces the unversioned format the server first spoke, in
+//! which clients sent a bare `join` and then `x y w h` floats, and the
+//! server answered with `x y w h` per entity. Old clients are migrated in
+//! three steps:
+//!
+//! 1. Now: `--legacy-clients` is on by default, and a client that joins
+//!    unversioned is served, byte for byte as before, by the frozen
+//!    [`legacy`] codec. The server logs a deprecation warning for each.
+//! 2. A later release turns `--legacy-clients` off by default; operators
+//!    with old clients left can still turn it on.
+//! 3. Once nobody does, the legacy codec goes.
+//!
+//! Without legacy support, an unversioned datagram is rejected as
+//! [`ProtocolError::Unversioned`], so the log says why an old client can't
+//! join rather than blaming a version number it never sent.
+//!
+//! [`legacy`]: crate::legacy
+
+use std::{
+    fmt,
+    str::{self, SplitWhitespace},
+};
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Largest datagram either side sends or accepts.
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// Most matches a [`Message::StatsReport`] lists the score of, so the report
+/// fits in [`MAX_MESSAGE_SIZE`] however many rooms are open.
+pub const MAX_REPORTED_SCORES: usize = 20;
+
+/// Number of decimal places every float is written with on the wire.
+/// A value re-parsed by the client is within half of the last place, 0.005
+/// field units, of the server's value, plus the f32 rounding of the parse.
+/// That stays under 0.0055 for positions, which are below 2^14, and under
+/// 0.007 for speeds, which are below 2^16.
+pub const TEXT_FLOAT_DECIMALS: usize = 2;
+
+/// Formats a float for the wire: fixed decimal places, never scientific
+/// notation, and no negative zero.
+pub struct TextFloat(pub f32);
+
+impl fmt::Display for TextFloat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Anything that rounds to zero is written as zero, whatever its sign.
+        let half_last_place = 0.5 / 10f32.powi(TEXT_FLOAT_DECIMALS as i32);
+        let value = if self.0.abs() <= half_last_place {
+            0.
+        } else {
+            self.0
+        };
+        write!(f, "{:.*}", TEXT_FLOAT_DECIMALS, value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityKind {
+    Paddle,
+    Ball,
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntityKind::Paddle => write!(f, "paddle"),
+            EntityKind::Ball => write!(f, "ball"),
+        }
+    }
+}
+
+/// One entity as sent to clients: `id kind x y width height dx dy`, with the
+/// velocity in field units per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntityState {
+    pub id: u32,
+    pub kind: EntityKind,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+impl fmt::Display for EntityState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {}",
+            self.id,
+            self.kind,
+            TextFloat(self.x),
+            TextFloat(self.y),
+            TextFloat(self.width),
+            TextFloat(self.height),
+            TextFloat(self.dx),
+            TextFloat(self.dy)
+        )
+    }
+}
+
+/// The players, and the packets received, from one class of peer address
+/// (loopback, private or public): `players/received` on the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeerTraffic {
+    pub players: u32,
+    pub packets_received: u64,
+}
+
+impl fmt::Display for PeerTraffic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.players, self.packets_received)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// Client asks for a paddle, optionally picking how it looks. A `solo`
+    /// player who would otherwise wait for an opponent plays the bot.
+    Join {
+        color: Option<u32>,
+        avatar: Option<u8>,
+        solo: bool,
+    },
+    /// Client is done playing and gives up its paddle.
+    Leave,
+    /// Server's reply to a join when every room is full and it may open no
+    /// more, so matchmakers can route the player elsewhere.
+    ServerAtCapacity,
+    /// Server's reply to a successful join. `dead_zone` and `curve` are how
+    /// the server normalizes axis input, so clients can mirror it; unlike
+    /// positions they are written at full precision, since a rounded dead
+    /// zone would normalize differently.
+    JoinAck {
+        paddle: EntityState,
+        color: u32,
+        avatar: u8,
+        lock_x: f32,
+        dead_zone: f32,
+        curve: f32,
+        /// The match the player was placed in.
+        room: u32,
+        server: String,
+    },
+    /// The other player left or timed out; the match is paused until someone
+    /// else joins.
+    OpponentLeft,
+    /// How the other player looks.
+    Opponent {
+        color: u32,
+        avatar: u8,
+    },
+    /// Client's absolute paddle position.
+    PlayerUpdate {
+        x: f32,
+        y: f32,
+    },
+    /// Client's analog paddle input in [-1, 1].
+    Axis {
+        value: f32,
+    },
+    /// Where the server put the client's paddle after it reported a position
+    /// the server wouldn't accept.
+    Correction {
+        x: f32,
+        y: f32,
+    },
+    /// Every entity except the recipient's own paddle, in entity id order.
+    /// `sequence` increases with every state packet the server sends, `tick`
+    /// is the room's simulation step the state was taken after, and
+    /// `server_time_ms` counts milliseconds since the server started. A
+    /// room's tick never goes backwards, even across a pause or a new match,
+    /// so clients can order and ack states by it.
+    State {
+        sequence: u64,
+        tick: u64,
+        server_time_ms: u64,
+        entities: Vec<EntityState>,
+    },
+    /// Entity `id` has left the match, e.g. with a player who left; its id
+    /// is never used again. Sent once to each player who was in the match
+    /// when it left and still is.
+    Despawn {
+        id: u32,
+    },
+    Score {
+        left: u32,
+        right: u32,
+    },
+    GameOver {
+        won: bool,
+    },
+    LobbyTimeout,
+    /// Latency probe. `client_time` is the client's own clock and comes back
+    /// unchanged in the pong.
+    Ping {
+        client_time: u64,
+    },
+    Pong {
+        client_time: u64,
+        server_time_ms: u64,
+    },
+    /// Asks for a [`Message::StatsReport`].
+    Stats,
+    /// One-line server summary. `rooms` is how many matches are open, out of
+    /// at most `max_rooms`; `scores` holds the left and right score of the
+    /// first [`MAX_REPORTED_SCORES`] of them; packet counters cover every
+    /// datagram since the server started.
+    /// Players and received packets are also broken down by the class of
+    /// the peer's address, so local test traffic stands out. `server` is the
+    /// server's build, as in [`Message::JoinAck`].
+    StatsReport {
+        rooms: u32,
+        max_rooms: u32,
+        players: u32,
+        balls_in_play: u32,
+        scores: Vec<(u32, u32)>,
+        uptime_secs: u64,
+        packets_received: u64,
+        packets_sent: u64,
+        loopback: PeerTraffic,
+        private: PeerTraffic,
+        public: PeerTraffic,
+        server: String,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    TooLarge(usize),
+    NotUtf8,
+    Empty,
+    /// The datagram has no version: it is from a client of the format before
+    /// version 1.
+    Unversioned,
+    WrongVersion(String),
+    MissingTag,
+    UnknownTag(String),
+    Malformed(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::TooLarge(size) => write!(
+                f,
+                "message of {} bytes exceeds the {} byte limit",
+                size, MAX_MESSAGE_SIZE
+            ),
+            ProtocolError::NotUtf8 => write!(f, "message is not valid UTF-8"),
+            ProtocolError::Empty => write!(f, "message is empty"),
+            ProtocolError::Unversioned => write!(
+                f,
+                "unversioned legacy message, expected protocol version {}",
+                PROTOCOL_VERSION
+            ),
+            ProtocolError::WrongVersion(version) => write!(
+                f,
+                "unsupported protocol version {}, expected {}",
+                version, PROTOCOL_VERSION
+            ),
+            ProtocolError::MissingTag => write!(f, "message has no type tag"),
+            ProtocolError::UnknownTag(tag) => write!(f, "unknown message type {}", tag),
+            ProtocolError::Malformed(tag) => write!(f, "malformed {} message", tag),
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", PROTOCOL_VERSION)?;
+        match self {
+            Message::Join {
+                color,
+                avatar,
+                solo,
+            } => {
+                write!(f, "join")?;
+                if *solo {
+                    write!(f, " solo")?;
+                }
+                if let Some(color) = color {
+                    write!(f, " color={:06X}", color)?;
+                }
+                if let Some(avatar) = avatar {
+                    write!(f, " avatar={}", avatar)?;
+                }
+                Ok(())
+            }
+            Message::Leave => write!(f, "leave"),
+            Message::ServerAtCapacity => write!(f, "server_at_capacity"),
+            Message::JoinAck {
+                paddle,
+                color,
+                avatar,
+                lock_x,
+                dead_zone,
+                curve,
+                room,
+                server,
+            } => write!(
+                f,
+                "join_ack {} color={:06X} avatar={} lock_x={} dead_zone={} curve={} room={} server={}",
+                paddle,
+                color,
+                avatar,
+                TextFloat(*lock_x),
+                dead_zone,
+                curve,
+                room,
+                server
+            ),
+            Message::OpponentLeft => write!(f, "opponent_left"),
+            Message::Opponent { color, avatar } => {
+                write!(f, "opponent color={:06X} avatar={}", color, avatar)
+            }
+            Message::PlayerUpdate { x, y } => {
+                write!(f, "update {} {}", TextFloat(*x), TextFloat(*y))
+            }
+            Message::Axis { value } => write!(f, "axis {}", TextFloat(*value)),
+            Message::Correction { x, y } => {
+                write!(f, "correct {} {}", TextFloat(*x), TextFloat(*y))
+            }
+            Message::State {
+                sequence,
+                tick,
+                server_time_ms,
+                entities,
+            } => {
+                write!(f, "state {} {} {}", sequence, tick, server_time_ms)?;
+                for entity in entities {
+                    write!(f, " {}", entity)?;
+                }
+                Ok(())
+            }
+            Message::Despawn { id } => write!(f, "despawn {}", id),
+            Message::Score { left, right } => write!(f, "score {} {}", left, right),
+            Message::GameOver { won: true } => write!(f, "win"),
+            Message::GameOver { won: false } => write!(f, "lose"),
+            Message::LobbyTimeout => write!(f, "lobby_timeout"),
+            Message::Ping { client_time } => write!(f, "ping {}", client_time),
+            Message::Pong {
+                client_time,
+                server_time_ms,
+            } => write!(f, "pong {} {}", client_time, server_time_ms),
+            Message::Stats => write!(f, "stats"),
+            Message::StatsReport {
+                rooms,
+                max_rooms,
+                players,
+                balls_in_play,
+                scores,
+                uptime_secs,
+                packets_received,
+                packets_sent,
+                loopback,
+                private,
+                public,
+                server,
+            } => {
+                write!(
+                    f,
+                    "stats_report rooms={} max_rooms={} players={} balls={} scores=",
+                    rooms, max_rooms, players, balls_in_play
+                )?;
+                for (i, (left, right)) in scores.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "," };
+                    write!(f, "{}{}-{}", separator, left, right)?;
+                }
+                write!(
+                    f,
+                    " uptime_secs={} received={} sent={} loopback={} private={} public={} server={}",
+                    uptime_secs, packets_received, packets_sent, loopback, private, public, server
+                )
+            }
+            Message::Error { reason } => write!(f, "error {}", reason),
+        }
+    }
+}
+
+/// Parses `color=RRGGBB`, requiring exactly six hex digits.
+fn parse_color(hex: &str) -> Option<u32> {
+    Some(hex)
+        .filter(|hex| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+}
+
+/// Reads the fields of one message, failing with that message's tag.
+struct Fields<'a> {
+    parts: SplitWhitespace<'a>,
+    tag: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn malformed(&self) -> ProtocolError {
+        ProtocolError::Malformed(self.tag.to_string())
+    }
+
+    fn next(&mut self) -> Result<&'a str, ProtocolError> {
+        self.parts.next().ok_or_else(|| self.malformed())
+    }
+
+    /// Reads a float, refusing NaN and infinities, which nothing in the game
+    /// can produce and which would poison the simulation.
+    fn float(&mut self) -> Result<f32, ProtocolError> {
+        self.next()?
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| self.malformed())
+    }
+
+    fn int<T: str::FromStr>(&mut self) -> Result<T, ProtocolError> {
+        self.next()?.parse::<T>().map_err(|_| self.malformed())
+    }
+
+    /// Reads a `key=value` field and returns the value.
+    fn option(&mut self, key: &str) -> Result<&'a str, ProtocolError> {
+        self.next()?
+            .strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix('='))
+            .ok_or_else(|| self.malformed())
+    }
+
+    fn color(&mut self) -> Result<u32, ProtocolError> {
+        parse_color(self.option("color")?).ok_or_else(|| self.malformed())
+    }
+
+    fn avatar(&mut self) -> Result<u8, ProtocolError> {
+        self.option("avatar")?
+            .parse::<u8>()
+            .map_err(|_| self.malformed())
+    }
+
+    fn entity(&mut self) -> Result<EntityState, ProtocolError> {
+        Ok(EntityState {
+            id: self.int()?,
+            kind: match self.next()? {
+                "paddle" => EntityKind::Paddle,
+                "ball" => EntityKind::Ball,
+                _ => return Err(self.malformed()),
+            },
+            x: self.float()?,
+            y: self.float()?,
+            width: self.float()?,
+            height: self.float()?,
+            dx: self.float()?,
+            dy: self.float()?,
+        })
+    }
+
+    /// Reads a finite `key=value` float field.
+    fn float_option(&mut self, key: &str) -> Result<f32, ProtocolError> {
+        self.option(key)?
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| self.malformed())
+    }
+
+    /// Reads a whole-number `key=value` field.
+    fn int_option<T: str::FromStr>(&mut self, key: &str) -> Result<T, ProtocolError> {
+        self.option(key)?.parse::<T>().map_err(|_| self.malformed())
+    }
+
+    /// Reads a `key=players/received` field.
+    fn traffic(&mut self, key: &str) -> Result<PeerTraffic, ProtocolError> {
+        self.option(key)?
+            .split_once('/')
+            .and_then(|(players, received)| {
+                Some(PeerTraffic {
+                    players: players.parse().ok()?,
+                    packets_received: received.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| self.malformed())
+    }
+
+    fn finish<T>(mut self, message: T) -> Result<T, ProtocolError> {
+        match self.parts.next() {
+            Some(_) => Err(self.malformed()),
+            None => Ok(message),
+        }
+    }
+}
+
+impl Message {
+    pub fn deserialize(bytes: &[u8]) -> Result<Message, ProtocolError> {
+        if bytes.len() > MAX_MESSAGE_SIZE {
+            return Err(ProtocolError::TooLarge(bytes.len()));
+        }
+
+        let text = str::from_utf8(bytes).map_err(|_| ProtocolError::NotUtf8)?;
+        let mut parts = text.split_whitespace();
+
+        let version = parts.next().ok_or(ProtocolError::Empty)?;
+        // The format before version 1 had neither versions nor tags: a bare
+        // `join`, or `x y w h` floats. No tag is a number.
+        let numeric_tag = parts
+            .clone()
+            .next()
+            .is_some_and(|tag| tag.parse::<f32>().is_ok());
+        if version.parse::<u32>().is_err() || numeric_tag {
+            return Err(ProtocolError::Unversioned);
+        }
+        if version.parse::<u8>() != Ok(PROTOCOL_VERSION) {
+            return Err(ProtocolError::WrongVersion(version.to_string()));
+        }
+
+        let tag = parts.next().ok_or(ProtocolError::MissingTag)?;
+        let mut fields = Fields { parts, tag };
+        match tag {
+            "join" => {
+                // Appearance options are best effort: anything malformed is
+                // dropped so the player gets defaults instead of an error.
+                let mut color = None;
+                let mut avatar = None;
+                let mut solo = false;
+                for option in fields.parts {
+                    if option == "solo" {
+                        solo = true;
+                    } else if let Some(hex) = option.strip_prefix("color=") {
+                        color = parse_color(hex);
+                    } else if let Some(id) = option.strip_prefix("avatar=") {
+                        avatar = id
+                            .parse::<u32>()
+                            .ok()
+                            .map(|id| id.min(u8::MAX as u32) as u8);
+                    }
+                }
+                Ok(Message::Join {
+                    color,
+                    avatar,
+                    solo,
+                })
+            }
+            "leave" => fields.finish(Message::Leave),
+            "server_at_capacity" => fields.finish(Message::ServerAtCapacity),
+            "join_ack" => {
+                let paddle = fields.entity()?;
+                let color = fields.color()?;
+                let avatar = fields.avatar()?;
+                let lock_x = fields.float_option("lock_x")?;
+                let dead_zone = fields.float_option("dead_zone")?;
+                let curve = fields.float_option("curve")?;
+                let room = fields
+                    .option("room")?
+                    .parse::<u32>()
+                    .map_err(|_| fields.malformed())?;
+                let server = fields.option("server")?.to_string();
+                fields.finish(Message::JoinAck {
+                    paddle,
+                    color,
+                    avatar,
+                    lock_x,
+                    dead_zone,
+                    curve,
+                    room,
+                    server,
+                })
+            }
+            "opponent_left" => fields.finish(Message::OpponentLeft),
+            "opponent" => {
+                let color = fields.color()?;
+                let avatar = fields.avatar()?;
+                fields.finish(Message::Opponent { color, avatar })
+            }
+            "update" => {
+                let x = fields.float()?;
+                let y = fields.float()?;
+                fields.finish(Message::PlayerUpdate { x, y })
+            }
+            "axis" => {
+                let value = fields.float()?;
+                fields.finish(Message::Axis { value })
+            }
+            "correct" => {
+                let x = fields.float()?;
+                let y = fields.float()?;
+                fields.finish(Message::Correction { x, y })
+            }
+            "state" => {
+                let sequence = fields.int()?;
+                let tick = fields.int()?;
+                let server_time_ms = fields.int()?;
+                let mut entities = Vec::new();
+                while fields.parts.clone().next().is_some() {
+                    entities.push(fields.entity()?);
+                }
+                Ok(Message::State {
+                    sequence,
+                    tick,
+                    server_time_ms,
+                    entities,
+                })
+            }
+            "despawn" => {
+                let id = fields.int()?;
+                fields.finish(Message::Despawn { id })
+            }
+            "score" => {
+                let left = fields.int()?;
+                let right = fields.int()?;
+                fields.finish(Message::Score { left, right })
+            }
+            "win" => fields.finish(Message::GameOver { won: true }),
+            "lose" => fields.finish(Message::GameOver { won: false }),
+            "lobby_timeout" => fields.finish(Message::LobbyTimeout),
+            "ping" => {
+                let client_time = fields.int()?;
+                fields.finish(Message::Ping { client_time })
+            }
+            "pong" => {
+                let client_time = fields.int()?;
+                let server_time_ms = fields.int()?;
+                fields.finish(Message::Pong {
+                    client_time,
+                    server_time_ms,
+                })
+            }
+            "stats" => fields.finish(Message::Stats),
+            "stats_report" => {
+                let rooms = fields.int_option("rooms")?;
+                let max_rooms = fields.int_option("max_rooms")?;
+                let players = fields.int_option("players")?;
+                let balls_in_play = fields.int_option("balls")?;
+                let scores = fields
+                    .option("scores")?
+                    .split(',')
+                    .filter(|score| !score.is_empty())
+                    .map(|score| {
+                        let (left, right) = score.split_once('-')?;
+                        Some((left.parse().ok()?, right.parse().ok()?))
+                    })
+                    .collect::<Option<Vec<(u32, u32)>>>()
+                    .ok_or_else(|| fields.malformed())?;
+                let uptime_secs = fields.int_option("uptime_secs")?;
+                let packets_received = fields.int_option("received")?;
+                let packets_sent = fields.int_option("sent")?;
+                let loopback = fields.traffic("loopback")?;
+                let private = fields.traffic("private")?;
+                let public = fields.traffic("public")?;
+                let server = fields.option("server")?.to_string();
+                fields.finish(Message::StatsReport {
+                    rooms,
+                    max_rooms,
+                    players,
+                    balls_in_play,
+                    scores,
+                    uptime_secs,
+                    packets_received,
+                    packets_sent,
+                    loopback,
+                    private,
+                    public,
+                    server,
+                })
+            }
+            "error" => Ok(Message::Error {
+                reason: fields.parts.collect::<Vec<&str>>().join(" "),
+            }),
+            _ => Err(ProtocolError::UnknownTag(tag.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{physics::Field, world::World};
+    use std::{net::SocketAddr, time::Instant};
+
+    fn roundtrip(message: &Message) -> Message {
+        Message::deserialize(message.to_string().as_bytes()).unwrap()
+    }
+
+    fn assert_close(sent: f32, received: f32) {
+        assert!(
+            (sent - received).abs() <= 0.005,
+            "{} came back as {}",
+            sent,
+            received
+        );
+    }
+
+    #[test]
+    fn full_game_state_parses_back() {
+        let mut world = World::new(Field::default(), 1200.);
+        let now = Instant::now();
+        let left: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let right: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        world.create_player(left, None, None, now);
+        world.create_player(right, None, None, now);
+        world.create_ball();
+        world.render_components[2].x += 0.3333;
+        world.speed_components[0].dy = -123.456;
+
+        let entities: Vec<EntityState> = (0..3).map(|i| world.entity_state(i)).collect();
+        let sent = Message::State {
+            sequence: 42,
+            tick: 7_000,
+            server_time_ms: 123_456,
+            entities: entities.clone(),
+        };
+        assert!(sent.to_string().len() <= MAX_MESSAGE_SIZE);
+
+        let (sequence, tick, server_time_ms, received) = match roundtrip(&sent) {
+            Message::State {
+                sequence,
+                tick,
+                server_time_ms,
+                entities,
+            } => (sequence, tick, server_time_ms, entities),
+            other => panic!("expected a state message, got {:?}", other),
+        };
+        assert_eq!((sequence, tick, server_time_ms), (42, 7_000, 123_456));
+        assert_eq!(received.len(), 3);
+        for (sent, received) in entities.iter().zip(received.iter()) {
+            assert_eq!((sent.id, sent.kind), (received.id, received.kind));
+            assert_close(sent.x, received.x);
+            assert_close(sent.y, received.y);
+            assert_close(sent.width, received.width);
+            assert_close(sent.height, received.height);
+            assert_close(sent.dx, received.dx);
+            assert_close(sent.dy, received.dy);
+        }
+        assert_eq!(received[2].kind, EntityKind::Ball);
+    }
+
+    fn entity(id: u32, kind: EntityKind) -> EntityState {
+        EntityState {
+            id,
+            kind,
+            x: 20.5,
+            y: -150.25,
+            width: 20.,
+            height: 100.,
+            dx: 1200.,
+            dy: -0.75,
+        }
+    }
+
+    /// One message of every variant. `variant` fails to compile when a
+    /// variant is added, and `every_variant_has_a_sample` fails until it is
+    /// added here too.
+    fn samples() -> Vec<Message> {
+        vec![
+            Message::Join {
+                color: Some(0x3399FF),
+                avatar: Some(3),
+                solo: true,
+            },
+            Message::Join {
+                color: None,
+                avatar: None,
+                solo: false,
+            },
+            Message::Leave,
+            Message::ServerAtCapacity,
+            Message::JoinAck {
+                paddle: entity(0, EntityKind::Paddle),
+                color: 0xFF5533,
+                avatar: 15,
+                lock_x: 760.,
+                dead_zone: 0.125,
+                curve: 2.5,
+                room: 7,
+                server: "0.1.0+abc1234.debug".to_string(),
+            },
+            Message::JoinAck {
+                paddle: entity(1, EntityKind::Paddle),
+                color: 0x000000,
+                avatar: 0,
+                lock_x: 20.,
+                dead_zone: 0.,
+                curve: 0.333,
+                room: 0,
+                server: "0.1.0+unknown.release".to_string(),
+            },
+            Message::OpponentLeft,
+            Message::Opponent {
+                color: 0,
+                avatar: 0,
+            },
+            Message::PlayerUpdate { x: 20., y: 150.5 },
+            Message::Axis { value: -0.25 },
+            Message::Correction { x: 760., y: 0. },
+            Message::State {
+                sequence: u64::MAX,
+                tick: u64::MAX,
+                server_time_ms: 0,
+                entities: vec![entity(1, EntityKind::Paddle), entity(2, EntityKind::Ball)],
+            },
+            Message::State {
+                sequence: 1,
+                tick: 0,
+                server_time_ms: 2,
+                entities: Vec::new(),
+            },
+            Message::Despawn { id: 2 },
+            Message::Score {
+                left: 10,
+                right: 11,
+            },
+            Message::GameOver { won: true },
+            Message::GameOver { won: false },
+            Message::LobbyTimeout,
+            Message::Ping { client_time: 123 },
+            Message::Pong {
+                client_time: 123,
+                server_time_ms: 456,
+            },
+            Message::Stats,
+            Message::StatsReport {
+                rooms: 2,
+                max_rooms: 50,
+                players: 3,
+                balls_in_play: 1,
+                scores: vec![(1, 2), (0, 0)],
+                uptime_secs: 60,
+                packets_received: 1000,
+                packets_sent: 2000,
+                loopback: PeerTraffic {
+                    players: 1,
+                    packets_received: 400,
+                },
+                private: PeerTraffic::default(),
+                public: PeerTraffic {
+                    players: 2,
+                    packets_received: 600,
+                },
+                server: "0.1.0+abc1234.release".to_string(),
+            },
+            Message::StatsReport {
+                rooms: 0,
+                max_rooms: 1,
+                players: 0,
+                balls_in_play: 0,
+                scores: Vec::new(),
+                uptime_secs: 0,
+                packets_received: 0,
+                packets_sent: 0,
+                loopback: PeerTraffic::default(),
+                private: PeerTraffic::default(),
+                public: PeerTraffic::default(),
+                server: "0.1.0+unknown.debug".to_string(),
+            },
+            Message::Error {
+                reason: "Game is full".to_string(),
+            },
+        ]
+    }
+
+    fn variant(message: &Message) -> usize {
+        match message {
+            Message::Join { .. } => 0,
+            Message::Leave => 1,
+            Message::ServerAtCapacity => 2,
+            Message::JoinAck { .. } => 3,
+            Message::OpponentLeft => 4,
+            Message::Opponent { .. } => 5,
+            Message::PlayerUpdate { .. } => 6,
+            Message::Axis { .. } => 7,
+            Message::Correction { .. } => 8,
+            Message::State { .. } => 9,
+            Message::Despawn { .. } => 10,
+            Message::Score { .. } => 11,
+            Message::GameOver { .. } => 12,
+            Message::LobbyTimeout => 13,
+            Message::Ping { .. } => 14,
+            Message::Pong { .. } => 15,
+            Message::Stats => 16,
+            Message::StatsReport { .. } => 17,
+            Message::Error { .. } => 18,
+        }
+    }
+
+    const VARIANTS: usize = 19;
+
+    #[test]
+    fn every_variant_has_a_sample() {
+        let mut covered = [false; VARIANTS];
+        for message in samples() {
+            covered[variant(&message)] = true;
+        }
+        assert!(covered.iter().all(|&covered| covered), "{:?}", covered);
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        for message in samples() {
+            assert_eq!(roundtrip(&message), message, "{}", message);
+        }
+    }
+
+    #[test]
+    fn every_variant_starts_with_the_version() {
+        for message in samples() {
+            let text = message.to_string();
+            assert!(text.starts_with("1 "), "{}", text);
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_malformed() {
+        for message in samples() {
+            // Join options and error reasons are free-form, so dropping the
+            // last word still leaves a valid message.
+            if matches!(message, Message::Join { .. } | Message::Error { .. }) {
+                continue;
+            }
+            let text = message.to_string();
+            let mut words: Vec<&str> = text.split_whitespace().collect();
+            if words.len() <= 2 {
+                continue;
+            }
+            words.pop();
+            let tag = words[1].to_string();
+            assert_eq!(
+                Message::deserialize(words.join(" ").as_bytes()),
+                Err(ProtocolError::Malformed(tag)),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_fields_are_malformed() {
+        for message in samples() {
+            if matches!(
+                message,
+                Message::Join { .. } | Message::Error { .. } | Message::State { .. }
+            ) {
+                continue;
+            }
+            let text = format!("{} extra", message);
+            assert!(
+                matches!(
+                    Message::deserialize(text.as_bytes()),
+                    Err(ProtocolError::Malformed(_))
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn messages_up_to_the_limit_are_accepted() {
+        let mut text = String::from("1 error ");
+        text.push_str(&"x".repeat(MAX_MESSAGE_SIZE - text.len()));
+        assert_eq!(text.len(), MAX_MESSAGE_SIZE);
+        assert!(Message::deserialize(text.as_bytes()).is_ok());
+
+        // Padding with trailing spaces doesn't change the message.
+        let mut padded = String::from("1 stats");
+        padded.push_str(&" ".repeat(MAX_MESSAGE_SIZE - padded.len()));
+        assert_eq!(Message::deserialize(padded.as_bytes()), Ok(Message::Stats));
+    }
+
+    #[test]
+    fn the_largest_stats_report_fits() {
+        let traffic = PeerTraffic {
+            players: u32::MAX,
+            packets_received: u64::MAX,
+        };
+        let report = Message::StatsReport {
+            rooms: u32::MAX,
+            max_rooms: u32::MAX,
+            players: u32::MAX,
+            balls_in_play: u32::MAX,
+            scores: vec![(u32::MAX, u32::MAX); MAX_REPORTED_SCORES],
+            uptime_secs: u64::MAX,
+            packets_received: u64::MAX,
+            packets_sent: u64::MAX,
+            loopback: traffic,
+            private: traffic,
+            public: traffic,
+            server: crate::BUILD_INFO.to_string(),
+        };
+        let size = report.to_string().len();
+        assert!(size <= MAX_MESSAGE_SIZE, "{} bytes", size);
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let text = format!("1 error {}", "x".repeat(MAX_MESSAGE_SIZE));
+        assert_eq!(
+            Message::deserialize(text.as_bytes()),
+            Err(ProtocolError::TooLarge(text.len()))
+        );
+    }
+
+    #[test]
+    fn framing_errors_are_reported() {
+        let cases: [(&[u8], ProtocolError); 10] = [
+            (b"", ProtocolError::Empty),
+            (b"   ", ProtocolError::Empty),
+            (b"\xff\xfe", ProtocolError::NotUtf8),
+            (b"join", ProtocolError::Unversioned),
+            (b"20 150.5 20 100", ProtocolError::Unversioned),
+            (b"1 100 20 100", ProtocolError::Unversioned),
+            (b"v1 leave", ProtocolError::Unversioned),
+            (b"2 leave", ProtocolError::WrongVersion("2".to_string())),
+            (b"1", ProtocolError::MissingTag),
+            (b"1 bogus", ProtocolError::UnknownTag("bogus".to_string())),
+        ];
+        for (bytes, error) in cases {
+            assert_eq!(Message::deserialize(bytes), Err(error));
+        }
+    }
+
+    /// Steps through every `stride`th f32 from `0` up to `limit`, on both
+    /// sides of zero.
+    fn floats_below(limit: f32, stride: u32) -> impl Iterator<Item = f32> {
+        (0..limit.to_bits())
+            .step_by(stride as usize)
+            .map(f32::from_bits)
+            .flat_map(|value| [value, -value])
+    }
+
+    fn assert_text_float_within(limit: f32, bound: f32) {
+        for value in floats_below(limit, 997) {
+            let text = TextFloat(value).to_string();
+            let (_, decimals) = text.split_once('.').unwrap();
+            assert_eq!(decimals.len(), TEXT_FLOAT_DECIMALS, "{}", text);
+            assert!(!text.contains('e') && text != "-0.00", "{}", text);
+
+            let parsed: f32 = text.parse().unwrap();
+            assert!(
+                (parsed - value).abs() <= bound,
+                "{} came back as {}",
+                value,
+                parsed
+            );
+        }
+    }
+
+    #[test]
+    fn positions_round_trip_within_the_documented_bound() {
+        assert_text_float_within(16384., 0.0055);
+    }
+
+    #[test]
+    fn speeds_round_trip_within_the_documented_bound() {
+        assert_text_float_within(65536., 0.007);
+    }
+
+    #[test]
+    fn tiny_floats_are_written_as_zero() {
+        for value in [0., -0., 1e-30, -1e-30, -0.004, -0.005] {
+            assert_eq!(TextFloat(value).to_string(), "0.00", "{}", value);
+        }
+    }
+
+    #[test]
+    fn axis_settings_are_written_exactly() {
+        for (dead_zone, curve) in [(0.125, 1.375), (0.1234567, 4.99999), (0.9, 0.2)] {
+            let sent = Message::JoinAck {
+                paddle: entity(0, EntityKind::Paddle),
+                color: 0,
+                avatar: 0,
+                lock_x: 20.,
+                dead_zone,
+                curve,
+                room: 0,
+                server: "0.1.0+abc1234.debug".to_string(),
+            };
+            let text = sent.to_string();
+            assert!(
+                text.contains(&format!(" dead_zone={} curve={} ", dead_zone, curve)),
+                "{}",
+                text
+            );
+            assert_eq!(roundtrip(&sent), sent);
+        }
+    }
+
+    #[test]
+    fn non_finite_floats_are_malformed() {
+        for text in ["1 update NaN 5", "1 update 5 inf", "1 axis -inf"] {
+            assert!(
+                matches!(
+                    Message::deserialize(text.as_bytes()),
+                    Err(ProtocolError::Malformed(_))
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_join_options_fall_back_to_defaults() {
+        assert_eq!(
+            Message::deserialize(b"1 join color=red avatar=-1 hat=yes"),
+            Ok(Message::Join {
+                color: None,
+                avatar: None,
+                solo: false,
+            })
+        );
+        assert_eq!(
+            Message::deserialize(b"1 join avatar=999"),
+            Ok(Message::Join {
+                color: None,
+                avatar: Some(u8::MAX),
+                solo: false,
+            })
+        );
+    }
+}
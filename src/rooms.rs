@@ -0,0 +1,136 @@
+//! Concurrent matches. Each room is an independent [`World`]; players are
+//! routed to theirs by source address.
+
+use crate::{log::Logger, physics::Field, world::World};
+use std::{collections::HashMap, fmt, net::SocketAddr};
+
+/// Identifies a room for as long as the server runs. Like entity ids, room
+/// ids are never reused, so a client's logged room always means one match.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct RoomId(pub u32);
+
+impl fmt::Display for RoomId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub struct Rooms {
+    rooms: HashMap<RoomId, World>,
+    players: HashMap<SocketAddr, RoomId>,
+    next_room_id: u32,
+    max_rooms: u32,
+    field: Field,
+    serve_speed_ups: f32,
+    logger: Logger,
+}
+
+impl Rooms {
+    /// Rooms of `field` serving at `serve_speed_ups`, of which at most
+    /// `max_rooms` are open at once.
+    pub fn new(field: Field, serve_speed_ups: f32, max_rooms: u32) -> Self {
+        Rooms {
+            rooms: HashMap::new(),
+            players: HashMap::new(),
+            next_room_id: 0,
+            max_rooms,
+            field,
+            serve_speed_ups,
+            logger: Logger::stdout(),
+        }
+    }
+
+    /// Sends this set of rooms's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// The room the peer at `source` is playing in, if any. A peer its room
+    /// has since dropped (it left, timed out or the match ended) is in none,
+    /// even before [`Rooms::tear_down`] has caught up.
+    pub fn room_of(&self, source: SocketAddr) -> Option<RoomId> {
+        let id = *self.players.get(&source)?;
+        self.rooms
+            .get(&id)
+            .filter(|world| world.player_entity(source).is_some())
+            .map(|_| id)
+    }
+
+    pub fn get(&self, id: RoomId) -> Option<&World> {
+        self.rooms.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: RoomId) -> Option<&mut World> {
+        self.rooms.get_mut(&id)
+    }
+
+    /// The oldest room with a free paddle, or a new one if every room is full.
+    /// A paddle the AI holds counts as free. Returns `None` if every room is
+    /// full and no more may be opened.
+    pub fn open_room(&mut self) -> Option<RoomId> {
+        let open = self
+            .rooms
+            .iter()
+            .filter(|(_, world)| world.player_addresses().count() < 2)
+            .map(|(id, _)| *id)
+            .min();
+        if open.is_some() {
+            return open;
+        }
+        if self.rooms.len() >= self.max_rooms as usize {
+            return None;
+        }
+
+        let id = RoomId(self.next_room_id);
+        self.next_room_id += 1;
+        self.rooms
+            .insert(id, World::new(self.field, self.serve_speed_ups));
+        self.logger.log(format_args!("Room {} opened", id));
+        Some(id)
+    }
+
+    /// How many rooms may be open at once.
+    pub fn max_rooms(&self) -> u32 {
+        self.max_rooms
+    }
+
+    /// Routes further packets from `source` to room `id`.
+    pub fn assign(&mut self, source: SocketAddr, id: RoomId) {
+        self.players.insert(source, id);
+    }
+
+    /// Every peer with a route, and the room its packets go to.
+    pub fn routes(&self) -> impl Iterator<Item = (SocketAddr, RoomId)> + '_ {
+        self.players.iter().map(|(source, id)| (*source, *id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RoomId, &World)> {
+        self.rooms.iter().map(|(id, world)| (*id, world))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (RoomId, &mut World)> {
+        self.rooms.iter_mut().map(|(id, world)| (*id, world))
+    }
+
+    /// Closes every room nobody is playing in any more, passing each one to
+    /// `closed`, and forgets the routes of peers that are no longer in their
+    /// room.
+    pub fn tear_down(&mut self, mut closed: impl FnMut(RoomId)) {
+        let logger = &self.logger;
+        self.rooms.retain(|id, world| {
+            let occupied = world.player_addresses().next().is_some();
+            if !occupied {
+                logger.log(format_args!("Room {} closed", id));
+                closed(*id);
+            }
+            occupied
+        });
+
+        let rooms = &self.rooms;
+        self.players.retain(|source, id| {
+            rooms
+                .get(id)
+                .is_some_and(|world| world.player_entity(*source).is_some())
+        });
+    }
+}
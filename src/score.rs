@@ -0,0 +1,60 @@
+//! Goals, points and the end of a match.
+
+use crate::{
+    log::Logger,
+    network::{NetworkSystem, Transport},
+    world::{Side, World},
+};
+use std::io;
+
+pub struct ScoreSystem {
+    winning_score: u32,
+    logger: Logger,
+}
+
+impl ScoreSystem {
+    pub fn new(winning_score: u32) -> Self {
+        ScoreSystem {
+            winning_score,
+            logger: Logger::stdout(),
+        }
+    }
+
+    /// Sends this system's log lines to `logger` instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// Awards a point to `scorer` and tells both players. Once someone
+    /// reaches the winning score they are told who won and the world is
+    /// reset so a new pair can join. The world is updated even if telling
+    /// the players fails.
+    pub fn goal<T: Transport>(
+        &self,
+        scorer: Side,
+        world: &mut World,
+        network_system: &mut NetworkSystem<T>,
+    ) -> io::Result<()> {
+        let id = world.player_on(scorer);
+        if let Some(score) = world
+            .score_components
+            .iter_mut()
+            .find(|score| Some(score.id) == id)
+        {
+            score.points += 1;
+            world.goals.push(score.id);
+        }
+        let mut sent = network_system.send_score(world);
+
+        if world.points(scorer) >= self.winning_score {
+            sent = sent.and(network_system.send_result(world, scorer));
+            self.logger.log(format_args!(
+                "Game over! Final score: {} {}",
+                world.points(Side::Left),
+                world.points(Side::Right)
+            ));
+            world.reset();
+        }
+        sent
+    }
+}
@@ -0,0 +1,461 @@
+//! The server loop: drain the transport, run every room's systems and
+//! broadcast state. A host can run it with [`Server::run_until`] or drive
+//! each iteration itself.
+
+use crate::{
+    bot::BotSystem,
+    collision::CollisionSystem,
+    config::Config,
+    control::ControlSystem,
+    invariants::{Bookkeeping, InvariantAuditor},
+    legacy,
+    lobby::{LobbySystem, TimeoutSystem},
+    log::Logger,
+    network::{NetworkSystem, Transport},
+    physics::{MAX_DT, NOMINAL_DT, PADDLE_SPEED_UPS},
+    protocol::{Message, ProtocolError},
+    rooms::{RoomId, Rooms},
+    score::ScoreSystem,
+    world::World,
+};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long [`Server::run_until`] sleeps after an update with no packets, so
+/// an idle server doesn't spin on a nonblocking socket.
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+pub struct Server<T: Transport = UdpSocket> {
+    rooms: Rooms,
+    network_system: NetworkSystem<T>,
+    collision_system: CollisionSystem,
+    control_system: ControlSystem,
+    score_system: ScoreSystem,
+    lobby_system: LobbySystem,
+    timeout_system: TimeoutSystem,
+    bot_system: BotSystem,
+    invariant_auditor: InvariantAuditor,
+    logger: Logger,
+    /// Whether a peer may join with the unversioned [`legacy`] protocol.
+    legacy_clients: bool,
+    send_interval: Duration,
+    last_tick: Instant,
+    last_send: Instant,
+    accumulator: f32,
+}
+
+impl<T: Transport> Server<T> {
+    pub fn new(config: &Config, network_system: NetworkSystem<T>, now: Instant) -> Self {
+        Server {
+            rooms: Rooms::new(config.field, config.ball_speed_ups, config.max_rooms),
+            network_system,
+            collision_system: CollisionSystem::new(),
+            control_system: ControlSystem::new(
+                config.axis_dead_zone,
+                config.axis_curve,
+                PADDLE_SPEED_UPS,
+            ),
+            score_system: ScoreSystem::new(config.winning_score),
+            lobby_system: LobbySystem::new(config.lobby_timeout, config.lobby_reset_on_activity),
+            timeout_system: TimeoutSystem::new(config.player_timeout),
+            bot_system: BotSystem::new(config.bot_wait, config.bot_reaction, PADDLE_SPEED_UPS),
+            invariant_auditor: InvariantAuditor::new(),
+            logger: Logger::stdout(),
+            legacy_clients: config.legacy_clients,
+            send_interval: Duration::from_secs_f32(1. / config.send_rate),
+            last_tick: now,
+            last_send: now,
+            accumulator: 0.,
+        }
+    }
+
+    /// Sends every log line of the server and its systems to `logger`
+    /// instead of stdout.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.rooms.set_logger(logger.clone());
+        self.network_system.set_logger(logger.clone());
+        self.score_system.set_logger(logger.clone());
+        self.lobby_system.set_logger(logger.clone());
+        self.bot_system.set_logger(logger.clone());
+        self.invariant_auditor.set_logger(logger.clone());
+        self.logger = logger;
+    }
+
+    pub fn rooms(&self) -> &Rooms {
+        &self.rooms
+    }
+
+    /// Invariant violations seen so far. Debug builds panic on the first one
+    /// instead, so this stays zero there.
+    pub fn invariant_violations(&self) -> u64 {
+        self.invariant_auditor.violations()
+    }
+
+    /// A [`Message::StatsReport`] as a `stats` request at `now` would get.
+    pub fn stats(&self, now: Instant) -> Message {
+        self.network_system.stats(&self.rooms, now)
+    }
+
+    /// Updates the server until `shutdown` is set, backing off while it is
+    /// idle, and returns the final stats. `clock` tells the time; a host
+    /// would pass `Instant::now`.
+    pub fn run_until(
+        &mut self,
+        shutdown: &AtomicBool,
+        mut clock: impl FnMut() -> Instant,
+    ) -> Message {
+        while !shutdown.load(Ordering::Relaxed) {
+            if !self.update(clock()) {
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+        self.stats(clock())
+    }
+
+    /// Handles every waiting packet, then advances the rooms to `now` and
+    /// broadcasts state if it is due. Returns whether any packet arrived, so
+    /// the caller can back off while the server is idle.
+    pub fn update(&mut self, now: Instant) -> bool {
+        let received = self.begin_update(now);
+
+        // Step every room in fixed ticks for the time that has passed. After
+        // a stall, anything beyond MAX_DT is dropped instead of caught up.
+        self.accumulator =
+            (self.accumulator + now.duration_since(self.last_tick).as_secs_f32()).min(MAX_DT);
+        self.last_tick = now;
+        while self.accumulator >= NOMINAL_DT {
+            self.tick();
+            self.accumulator -= NOMINAL_DT;
+        }
+
+        self.end_update(now);
+        received
+    }
+
+    /// Like [`Server::update`], but advances the rooms by exactly one fixed
+    /// tick however much time has passed, for a host that keeps its own
+    /// schedule. Drive a server with one or the other, not both.
+    pub fn tick_once(&mut self, now: Instant) -> bool {
+        let received = self.begin_update(now);
+        self.tick();
+        self.last_tick = now;
+        self.end_update(now);
+        received
+    }
+
+    /// Audits what the previous update left, handles every waiting packet and
+    /// runs the per-room checks. Returns whether any packet arrived.
+    fn begin_update(&mut self, now: Instant) -> bool {
+        // Each tick is audited as it ends. Packets, checks and teardown also
+        // change the worlds between ticks, so audit what the previous update
+        // left behind too.
+        for (_, world) in self.rooms.iter() {
+            self.invariant_auditor.audit(world);
+        }
+
+        let mut received = false;
+        loop {
+            let (size, source) = match self.network_system.receive() {
+                Ok((size, source)) => (size, source),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.logger.log(format_args!("Failed to receive: {}", e));
+                    break;
+                }
+            };
+            received = true;
+            if let Err(e) = self.handle_packet(size, source, now) {
+                self.logger
+                    .log(format_args!("Failed to answer {}: {}", source, e));
+            }
+        }
+
+        for (room, world) in self.rooms.iter_mut() {
+            let bot = self
+                .bot_system
+                .check(room, world, &mut self.network_system, now);
+            let timed_out = self
+                .timeout_system
+                .check(world, &mut self.network_system, now);
+            let lobby = self
+                .lobby_system
+                .check(room, world, &mut self.network_system, now);
+            if let Err(e) = bot.and(timed_out).and(lobby) {
+                self.logger
+                    .log(format_args!("Failed to notify room {}: {}", room, e));
+            }
+        }
+        received
+    }
+
+    /// Advances every room by one fixed tick.
+    fn tick(&mut self) {
+        for (room, world) in self.rooms.iter_mut() {
+            world.tick += 1;
+            self.bot_system.update(room, world, NOMINAL_DT);
+            self.control_system.step(world, NOMINAL_DT);
+            self.collision_system.resolve(world, NOMINAL_DT);
+            if let Some(scorer) = self.collision_system.ball_out_of_bounds(world) {
+                let scored = self
+                    .score_system
+                    .goal(scorer, world, &mut self.network_system);
+                if let Err(e) = scored {
+                    self.logger.log(format_args!(
+                        "Failed to announce goal in room {}: {}",
+                        room, e
+                    ));
+                }
+            }
+
+            if world.render_components.len() == 2 {
+                world.create_ball();
+            }
+            self.invariant_auditor.audit(world);
+        }
+    }
+
+    /// Announces despawns, closes empty rooms and broadcasts state if it is
+    /// due at `now`.
+    fn end_update(&mut self, now: Instant) {
+        for (room, world) in self.rooms.iter_mut() {
+            if let Err(e) = self.network_system.send_despawns(world) {
+                self.logger.log(format_args!(
+                    "Failed to announce despawns in room {}: {}",
+                    room, e
+                ));
+            }
+        }
+
+        // Room ids are never reused, so anything a system keeps per room
+        // would otherwise outlive it forever.
+        let (lobby_system, bot_system) = (&mut self.lobby_system, &mut self.bot_system);
+        self.rooms.tear_down(|room| {
+            lobby_system.forget(room);
+            bot_system.forget(room);
+        });
+        let rooms = &self.rooms;
+        self.network_system
+            .forget_legacy_peers(|peer| rooms.room_of(peer).is_some());
+        self.invariant_auditor.audit_bookkeeping(&Bookkeeping {
+            rooms: &self.rooms,
+            lobby_system: &self.lobby_system,
+            bot_system: &self.bot_system,
+        });
+
+        if now.duration_since(self.last_send) >= self.send_interval {
+            for (room, world) in self.rooms.iter() {
+                if let Err(e) = self.network_system.broadcast_state(world, now) {
+                    self.logger
+                        .log(format_args!("Failed to send state in room {}: {}", room, e));
+                }
+            }
+            self.last_send = now;
+        }
+    }
+
+    /// Handles the datagram of `size` bytes the network system just received
+    /// from `source`. Errors are failures to reply; requests the server
+    /// refuses are answered with an error message instead.
+    fn handle_packet(&mut self, size: usize, source: SocketAddr, now: Instant) -> io::Result<()> {
+        let room = self.rooms.room_of(source);
+        if let Some(world) = room.and_then(|room| self.rooms.get_mut(room)) {
+            world.touch(source, now);
+        }
+
+        if self.legacy_clients {
+            if let Some(request) = self.network_system.parse_legacy_request(size, source) {
+                return self.handle_legacy_request(request, source, room, now);
+            }
+        }
+
+        let network_system = &mut self.network_system;
+        let request = match network_system.parse_request(size) {
+            Ok(request) => request,
+            Err(ProtocolError::Unversioned) if !self.legacy_clients => {
+                self.logger.log(format_args!(
+                    "Rejected legacy client {}: legacy clients are off",
+                    source
+                ));
+                return network_system.send_error(&ProtocolError::Unversioned.to_string(), source);
+            }
+            Err(e) => {
+                self.logger
+                    .log(format_args!("Rejected packet from {}: {}", source, e));
+                return network_system.send_error(&e.to_string(), source);
+            }
+        };
+
+        // Monitoring requests work whether or not the peer has joined and
+        // leave the rooms alone, though a player's ping still counts as a
+        // sign of life above.
+        match request {
+            Message::Ping { client_time } => {
+                return network_system.send_pong(client_time, source, now)
+            }
+            Message::Stats => return network_system.send_stats(&self.rooms, source, now),
+            _ => {}
+        }
+
+        if let Message::Join {
+            color,
+            avatar,
+            solo,
+        } = request
+        {
+            if room.is_some() {
+                return network_system.send_error("Already joined", source);
+            }
+            return self.join(color, avatar, solo, source, now);
+        }
+
+        let (room, world) = match room.and_then(|room| Some((room, self.rooms.get_mut(room)?))) {
+            Some(joined) => joined,
+            None => return network_system.send_error("Not joined", source),
+        };
+
+        let handled = match request {
+            Message::Leave => network_system.handle_leave(source, world),
+            Message::Axis { value } => match self.control_system.apply_axis(value, source, world) {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    self.logger.log(format_args!("Failed to apply axis: {}", e));
+                    network_system.send_error(&e.to_string(), source)
+                }
+            },
+            Message::PlayerUpdate { x, y } => move_paddle(
+                x,
+                y,
+                source,
+                world,
+                &self.control_system,
+                network_system,
+                now,
+            ),
+            _ => network_system.send_error("Unexpected message from a client", source),
+        };
+        self.lobby_system.activity(room, source, world, now);
+        handled
+    }
+
+    /// Puts the peer at `source`, which hasn't joined yet, in the oldest room
+    /// with a free paddle, opening one if need be.
+    fn join(
+        &mut self,
+        color: Option<u32>,
+        avatar: Option<u8>,
+        solo: bool,
+        source: SocketAddr,
+        now: Instant,
+    ) -> io::Result<()> {
+        let network_system = &mut self.network_system;
+        let room = match self.rooms.open_room() {
+            Some(room) => room,
+            None => {
+                self.logger.log(format_args!(
+                    "Refused {}: all {} rooms are full",
+                    source,
+                    self.rooms.max_rooms()
+                ));
+                return network_system.send(&Message::ServerAtCapacity, source);
+            }
+        };
+        let world = match self.rooms.get_mut(room) {
+            Some(world) => world,
+            None => return network_system.send_error("No room available", source),
+        };
+        self.bot_system.make_way(room, world);
+        let mut joined = network_system.handle_join(
+            color,
+            avatar,
+            source,
+            room,
+            world,
+            &self.control_system,
+            now,
+        );
+        // The player is in the room even if a reply to the join failed.
+        let created = world.player_entity(source).is_some();
+        if created && solo && world.render_components.len() == 1 {
+            joined = joined.and(self.bot_system.join(room, world, network_system));
+        }
+        if created {
+            self.rooms.assign(source, room);
+        }
+        match joined {
+            Err(e) if !created => network_system.send_error(&e.to_string(), source),
+            joined => joined,
+        }
+    }
+
+    /// Handles a request in the unversioned [`legacy`] protocol. Legacy
+    /// clients understand no errors, so a request that fails goes unanswered,
+    /// as it always did.
+    fn handle_legacy_request(
+        &mut self,
+        request: legacy::Request,
+        source: SocketAddr,
+        room: Option<RoomId>,
+        now: Instant,
+    ) -> io::Result<()> {
+        match request {
+            legacy::Request::Join => {
+                if room.is_some() {
+                    return Ok(());
+                }
+                if self.network_system.add_legacy_peer(source) {
+                    self.logger.log(format_args!(
+                        "Legacy client {} joined without a protocol version; \
+                         legacy support is deprecated and will be off by default \
+                         in a later release",
+                        source
+                    ));
+                }
+                self.join(None, None, false, source, now)
+            }
+            legacy::Request::Update { x, y } => {
+                let (room, world) =
+                    match room.and_then(|room| Some((room, self.rooms.get_mut(room)?))) {
+                        Some(joined) => joined,
+                        None => return Ok(()),
+                    };
+                let network_system = &mut self.network_system;
+                let moved = move_paddle(
+                    x,
+                    y,
+                    source,
+                    world,
+                    &self.control_system,
+                    network_system,
+                    now,
+                );
+                self.lobby_system.activity(room, source, world, now);
+                moved.and(network_system.send_legacy_state(world, source))
+            }
+        }
+    }
+}
+
+/// Moves the paddle of the player at `source` in `world` to where its client
+/// says it is, and tells the client if the server put it elsewhere.
+fn move_paddle<T: Transport>(
+    x: f32,
+    y: f32,
+    source: SocketAddr,
+    world: &mut World,
+    control_system: &ControlSystem,
+    network_system: &mut NetworkSystem<T>,
+    now: Instant,
+) -> io::Result<()> {
+    let since_last_update = world
+        .touch_position(source, now)
+        .map_or(NOMINAL_DT, |at| now.duration_since(at).as_secs_f32());
+    match control_system.update_players(x, y, since_last_update, source, world) {
+        Some((x, y)) => network_system.send_correction(x, y, source),
+        None => Ok(()),
+    }
+}
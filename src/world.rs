@@ -0,0 +1,481 @@
+//! The entities of one match and the components attached to them.
+//!
+//! Every component is stored in its own Vec on [`World`] and tagged with the
+//! [`EntityId`] it belongs to.
+
+use crate::{
+    physics::{Field, BALL_SIZE, PADDLE_HEIGHT, PADDLE_MARGIN, PADDLE_SPAWN_Y, PADDLE_WIDTH},
+    protocol::{EntityKind, EntityState},
+};
+use std::{net::SocketAddr, time::Instant};
+
+/// Identifies an entity for the lifetime of a match. Ids are handed out in
+/// increasing order and never reused, so clients can key interpolation state
+/// by id (or by snapshot position, which follows id order).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct EntityId(pub u32);
+
+/// Highest avatar id clients know how to draw; larger requests are clamped.
+const MAX_AVATAR_ID: u8 = 15;
+
+/// Goals recorded before a match's history has to grow, so scoring doesn't
+/// allocate mid-match. A match to the default 11 points has at most 21.
+const GOALS_RESERVED: usize = 21;
+
+/// Colors handed to the left and right player when they don't pick one.
+const DEFAULT_COLORS: [u32; 2] = [0x3399FF, 0xFF5533];
+
+pub struct World {
+    pub render_components: Vec<Renderable>,
+    pub speed_components: Vec<Speed>,
+    pub appearance_components: Vec<Appearance>,
+    pub ownership_components: Vec<Ownership>,
+    pub score_components: Vec<Score>,
+    pub last_seen_components: Vec<LastSeen>,
+    /// Entities removed mid-match that the players haven't been told about
+    /// yet.
+    pub despawned: Vec<Despawned>,
+    /// The paddle credited with each goal of this match, in order.
+    pub goals: Vec<EntityId>,
+    pub next_entity_id: u32,
+    /// Simulation steps this room has run. It only ever goes up for as long
+    /// as the room is open: a paused match keeps ticking, and a reset for a
+    /// new match carries it over.
+    pub tick: u64,
+    pub field: Field,
+    pub serve_speed_ups: f32,
+}
+
+impl World {
+    pub fn new(field: Field, serve_speed_ups: f32) -> Self {
+        World {
+            render_components: Vec::new(),
+            speed_components: Vec::new(),
+            appearance_components: Vec::new(),
+            ownership_components: Vec::new(),
+            score_components: Vec::new(),
+            last_seen_components: Vec::new(),
+            despawned: Vec::new(),
+            goals: Vec::with_capacity(GOALS_RESERVED),
+            next_entity_id: 0,
+            tick: 0,
+            field,
+            serve_speed_ups,
+        }
+    }
+
+    /// Clears every entity for a new match, keeping the configured field,
+    /// serve speed and tick.
+    pub fn reset(&mut self) {
+        let tick = self.tick;
+        *self = World::new(self.field, self.serve_speed_ups);
+        self.tick = tick;
+    }
+
+    /// The paddle controlled by the peer at `source`, if it has joined.
+    pub fn player_entity(&self, source: SocketAddr) -> Option<EntityId> {
+        self.owned_by(Owner::Player(source))
+    }
+
+    /// The paddle the server's AI plays, if it is in the match.
+    pub fn bot_entity(&self) -> Option<EntityId> {
+        self.owned_by(Owner::Ai)
+    }
+
+    fn owned_by(&self, owner: Owner) -> Option<EntityId> {
+        self.ownership_components
+            .iter()
+            .find(|ownership| ownership.owner == owner)
+            .map(|ownership| ownership.id)
+    }
+
+    pub fn owner(&self, id: EntityId) -> Option<Owner> {
+        self.ownership_components
+            .iter()
+            .find(|ownership| ownership.id == id)
+            .map(|ownership| ownership.owner)
+    }
+
+    /// The address of the peer controlling entity `id`, if a player does.
+    pub fn player_address(&self, id: EntityId) -> Option<SocketAddr> {
+        match self.owner(id)? {
+            Owner::Player(source) => Some(source),
+            Owner::Ai | Owner::Server => None,
+        }
+    }
+
+    /// The paddle defending `side`'s goal.
+    pub fn player_on(&self, side: Side) -> Option<EntityId> {
+        self.render_components
+            .iter()
+            .filter(|renderable| !renderable.is_ball())
+            .find(|paddle| Side::of(paddle, &self.field) == side)
+            .map(|paddle| paddle.id)
+    }
+
+    pub fn points(&self, side: Side) -> u32 {
+        let id = self.player_on(side);
+        self.score_components
+            .iter()
+            .find(|score| Some(score.id) == id)
+            .map_or(0, |score| score.points)
+    }
+
+    /// The addresses of every peer playing; the AI's paddle has none.
+    pub fn player_addresses(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.ownership_components
+            .iter()
+            .filter_map(|ownership| match ownership.owner {
+                Owner::Player(source) => Some(source),
+                Owner::Ai | Owner::Server => None,
+            })
+    }
+
+    /// The wire view of the entity whose components are at `index`.
+    pub fn entity_state(&self, index: usize) -> EntityState {
+        let renderable = &self.render_components[index];
+        let speed = &self.speed_components[index];
+        EntityState {
+            id: renderable.id.0,
+            kind: renderable.kind,
+            x: renderable.x,
+            y: renderable.y,
+            width: renderable.width,
+            height: renderable.height,
+            dx: speed.dx,
+            dy: speed.dy,
+        }
+    }
+
+    /// Records that the peer at `source` was heard from at `now`.
+    pub fn touch(&mut self, source: SocketAddr, now: Instant) {
+        if let Some(last_seen) = self.last_seen_mut(source) {
+            last_seen.at = now;
+        }
+    }
+
+    /// Records that the peer at `source` reported its paddle's position at
+    /// `now`, and returns when it last did before that. Other packets in
+    /// between don't count, so the time returned is how long the paddle has
+    /// had to get there.
+    pub fn touch_position(&mut self, source: SocketAddr, now: Instant) -> Option<Instant> {
+        let last_seen = self.last_seen_mut(source)?;
+        Some(std::mem::replace(&mut last_seen.position_at, now))
+    }
+
+    fn last_seen_mut(&mut self, source: SocketAddr) -> Option<&mut LastSeen> {
+        let id = self.player_entity(source)?;
+        self.last_seen_components
+            .iter_mut()
+            .find(|last_seen| last_seen.id == id)
+    }
+
+    fn allocate_id(&mut self) -> EntityId {
+        let id = EntityId(self.next_entity_id);
+        self.next_entity_id += 1;
+        id
+    }
+
+    pub fn create_ball(&mut self) {
+        if self.render_components.len() == 2 {
+            let id = self.allocate_id();
+            let ball = Renderable {
+                id,
+                kind: EntityKind::Ball,
+                x: self.field.width / 2.,
+                y: self.field.height / 2.,
+                width: BALL_SIZE,
+                height: BALL_SIZE,
+            };
+            let velocity = Speed {
+                id,
+                dx: self.serve_speed_ups,
+                dy: 0.,
+            };
+            self.render_components.push(ball);
+            self.speed_components.push(velocity);
+            self.ownership_components.push(Ownership {
+                id,
+                owner: Owner::Server,
+            });
+        }
+    }
+
+    /// Spawns a paddle for `source` on the left if that side is free, and on
+    /// the right otherwise. Returns the index of the paddle's components.
+    pub fn create_player(
+        &mut self,
+        source: SocketAddr,
+        color: Option<u32>,
+        avatar: Option<u8>,
+        now: Instant,
+    ) -> usize {
+        let index = self.create_paddle(Owner::Player(source), color, avatar);
+        let id = self.render_components[index].id;
+        self.last_seen_components.push(LastSeen {
+            id,
+            at: now,
+            position_at: now,
+        });
+        index
+    }
+
+    /// Spawns a paddle for the server's AI on the free side, like
+    /// [`World::create_player`] does for a peer.
+    pub fn create_bot(&mut self) -> usize {
+        self.create_paddle(Owner::Ai, None, None)
+    }
+
+    fn create_paddle(&mut self, owner: Owner, color: Option<u32>, avatar: Option<u8>) -> usize {
+        let id = self.allocate_id();
+        let side = match self.player_on(Side::Left) {
+            None => Side::Left,
+            Some(_) => Side::Right,
+        };
+        let x = match side {
+            Side::Left => PADDLE_MARGIN,
+            Side::Right => self.field.width - PADDLE_MARGIN - PADDLE_WIDTH,
+        };
+        let player = Renderable {
+            id,
+            kind: EntityKind::Paddle,
+            x,
+            y: PADDLE_SPAWN_Y,
+            width: PADDLE_WIDTH,
+            height: PADDLE_HEIGHT,
+        };
+        let speed = Speed { id, dx: 0., dy: 0. };
+        let appearance = self.resolve_appearance(id, side, color, avatar);
+        self.render_components.push(player);
+        self.speed_components.push(speed);
+        self.appearance_components.push(appearance);
+        self.ownership_components.push(Ownership { id, owner });
+        self.score_components.push(Score { id, points: 0 });
+
+        self.render_components.len() - 1
+    }
+
+    /// Removes every component belonging to entity `id`, and queues it to
+    /// be announced to the players still in the match.
+    fn despawn(&mut self, id: EntityId) {
+        self.render_components
+            .retain(|renderable| renderable.id != id);
+        self.speed_components.retain(|speed| speed.id != id);
+        self.appearance_components
+            .retain(|appearance| appearance.id != id);
+        self.ownership_components
+            .retain(|ownership| ownership.id != id);
+        self.score_components.retain(|score| score.id != id);
+        self.last_seen_components
+            .retain(|last_seen| last_seen.id != id);
+        let recipients = self.player_addresses().collect();
+        self.despawned.push(Despawned { id, recipients });
+    }
+
+    /// Removes the paddle of the peer at `source`, along with the ball since
+    /// a match can't go on one-sided. The remaining player's score is reset
+    /// so the next opponent starts a fresh match.
+    pub fn remove_player(&mut self, source: SocketAddr) -> Option<EntityId> {
+        let id = self.player_entity(source)?;
+        self.remove_paddle(id);
+        Some(id)
+    }
+
+    /// Removes the AI's paddle the way [`World::remove_player`] removes a
+    /// peer's.
+    pub fn remove_bot(&mut self) -> Option<EntityId> {
+        let id = self.bot_entity()?;
+        self.remove_paddle(id);
+        Some(id)
+    }
+
+    fn remove_paddle(&mut self, id: EntityId) {
+        self.despawn(id);
+
+        if let Some(ball) = self
+            .render_components
+            .iter()
+            .find(|renderable| renderable.is_ball())
+            .map(|ball| ball.id)
+        {
+            self.despawn(ball);
+        }
+        for score in self.score_components.iter_mut() {
+            score.points = 0;
+        }
+        self.goals.clear();
+    }
+
+    /// Fills in defaults for anything the player didn't pick, and nudges the
+    /// color away from any player already in the world so the two paddles
+    /// never look the same.
+    fn resolve_appearance(
+        &self,
+        id: EntityId,
+        side: Side,
+        color: Option<u32>,
+        avatar: Option<u8>,
+    ) -> Appearance {
+        let slot = match side {
+            Side::Left => 0,
+            Side::Right => 1,
+        };
+        let mut color = color.unwrap_or(DEFAULT_COLORS[slot]);
+        if self
+            .appearance_components
+            .iter()
+            .any(|appearance| appearance.color == color)
+        {
+            color ^= 0x808080;
+        }
+
+        Appearance {
+            color,
+            avatar: avatar.unwrap_or(0).min(MAX_AVATAR_ID),
+            id,
+        }
+    }
+}
+
+pub struct Renderable {
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Renderable {
+    pub fn is_ball(&self) -> bool {
+        self.kind == EntityKind::Ball
+    }
+}
+
+pub struct Speed {
+    pub id: EntityId,
+    pub dy: f32,
+    pub dx: f32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Appearance {
+    pub id: EntityId,
+    pub color: u32,
+    pub avatar: u8,
+}
+
+/// Who drives an entity. Only player paddles are tied to a peer address;
+/// the paddle the server plays itself is `Ai`, and anything else the server
+/// spawns, like the ball, is `Server`. A player is keyed by address rather
+/// than a session id because the address is all the protocol identifies a
+/// peer by: there is no session handshake, and a client that changes port
+/// has to join again anyway.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Owner {
+    Player(SocketAddr),
+    Ai,
+    Server,
+}
+
+pub struct Ownership {
+    pub id: EntityId,
+    pub owner: Owner,
+}
+
+pub struct Score {
+    pub id: EntityId,
+    pub points: u32,
+}
+
+/// When a player's peer last sent anything, for evicting silent players, and
+/// when it last sent its paddle's position, for limiting how far it moved.
+pub struct LastSeen {
+    pub id: EntityId,
+    pub at: Instant,
+    pub position_at: Instant,
+}
+
+/// An entity removed mid-match, and the players who were in the match when
+/// it went. Anyone who joins later never saw it, so isn't told.
+pub struct Despawned {
+    pub id: EntityId,
+    pub recipients: Vec<SocketAddr>,
+}
+
+/// Which half of the field a paddle defends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn of(paddle: &Renderable, field: &Field) -> Self {
+        if paddle.x + paddle.width / 2. < field.width / 2. {
+            Side::Left
+        } else {
+            Side::Right
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Joins a player per entry of `picks` and returns the appearances they
+    /// ended up with.
+    fn join(picks: &[(Option<u32>, Option<u8>)]) -> Vec<(u32, u8)> {
+        let mut world = World::new(Field::default(), 1200.);
+        for (port, &(color, avatar)) in (1000..).zip(picks) {
+            world.create_player(source(port), color, avatar, Instant::now());
+        }
+        world
+            .appearance_components
+            .iter()
+            .map(|appearance| (appearance.color, appearance.avatar))
+            .collect()
+    }
+
+    #[test]
+    fn players_who_pick_nothing_get_their_side_defaults() {
+        assert_eq!(
+            join(&[(None, None), (None, None)]),
+            [(DEFAULT_COLORS[0], 0), (DEFAULT_COLORS[1], 0)]
+        );
+    }
+
+    #[test]
+    fn identical_colors_are_separated() {
+        let appearances = join(&[(Some(0x123456), Some(1)), (Some(0x123456), Some(2))]);
+        assert_eq!(appearances[0], (0x123456, 1));
+        assert_eq!(appearances[1], (0x123456 ^ 0x808080, 2));
+
+        // Picking the color the other side got by default clashes too.
+        let appearances = join(&[(None, None), (Some(DEFAULT_COLORS[0]), None)]);
+        assert_ne!(appearances[0].0, appearances[1].0);
+    }
+
+    #[test]
+    fn a_new_match_keeps_counting_ticks() {
+        let mut world = World::new(Field::default(), 1200.);
+        world.create_player(source(1000), None, None, Instant::now());
+        world.tick = 99;
+        world.reset();
+        assert!(world.render_components.is_empty());
+        assert_eq!(world.tick, 99);
+    }
+
+    #[test]
+    fn unknown_avatars_are_clamped() {
+        assert_eq!(
+            join(&[(None, Some(200))]),
+            [(DEFAULT_COLORS[0], MAX_AVATAR_ID)]
+        );
+    }
+}
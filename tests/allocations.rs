@@ -0,0 +1,166 @@
+//! The steady-state tick must not touch the heap. A counting global allocator
+//! measures every allocation made on the test's own thread.
+//!
+//! [`MemoryTransport`] allocates a Vec for every datagram it passes on, so
+//! the server here sends through [`Muted`], which drops datagrams while a
+//! measurement runs and counts them instead.
+
+use pong_server::{
+    config::Config,
+    network::{MemoryTransport, NetworkSystem, Transport},
+    physics::Field,
+    protocol::Message,
+    server::Server,
+    world::World,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static MUTED: Cell<bool> = const { Cell::new(false) };
+    static DROPPED: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    // Thread-local storage may already be gone while a thread exits.
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f` with outgoing datagrams dropped, and returns how many heap
+/// allocations it made and how many datagrams it sent.
+fn measure(f: impl FnOnce()) -> (usize, usize) {
+    MUTED.with(|muted| muted.set(true));
+    DROPPED.with(|dropped| dropped.set(0));
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+    MUTED.with(|muted| muted.set(false));
+    (allocations, DROPPED.with(Cell::get))
+}
+
+/// A [`MemoryTransport`] that drops what it is asked to send while
+/// [`measure`] runs.
+struct Muted(MemoryTransport);
+
+impl Transport for Muted {
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.0.recv_from(buf)
+    }
+
+    fn send_to(&mut self, buf: &[u8], destination: SocketAddr) -> io::Result<usize> {
+        if MUTED.with(Cell::get) {
+            DROPPED.with(|dropped| dropped.set(dropped.get() + 1));
+            return Ok(buf.len());
+        }
+        self.0.send_to(buf, destination)
+    }
+}
+
+fn client(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn the_steady_state_tick_does_not_allocate() {
+    let mut now = Instant::now();
+    let (transport, peer) = MemoryTransport::new();
+    let network_system = NetworkSystem::new(Muted(transport), now);
+    let mut server = Server::new(&Config::default(), network_system, now);
+
+    // Two players join and the ball is served.
+    for port in [5000, 5001] {
+        let join = Message::Join {
+            color: None,
+            avatar: None,
+            solo: false,
+        };
+        peer.to_server
+            .send((client(port), join.to_string().into_bytes()))
+            .unwrap();
+    }
+    let frame = Duration::from_millis(10);
+    for _ in 0..10 {
+        now += frame;
+        server.update(now);
+    }
+    assert!(server
+        .rooms()
+        .iter()
+        .all(|(_, world)| world.render_components.len() == 3));
+
+    // A second of play with nothing arriving: ticks, collisions, audits and
+    // state broadcasts to both players.
+    let (allocations, sent) = measure(|| {
+        for _ in 0..100 {
+            now += frame;
+            server.update(now);
+        }
+    });
+    assert!(sent >= 30, "only {} state packets were sent", sent);
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn encoding_a_snapshot_does_not_allocate() {
+    let now = Instant::now();
+    let (transport, _peer) = MemoryTransport::new();
+    let mut network_system = NetworkSystem::new(Muted(transport), now);
+    let mut world = World::new(Field::default(), 1200.);
+    world.create_player(client(5000), None, None, now);
+    world.create_player(client(5001), None, None, now);
+    world.create_ball();
+
+    // The first encode may size the scratch buffers.
+    measure(|| {
+        network_system
+            .send_state(&world, client(5000), now)
+            .unwrap()
+    });
+    let (allocations, sent) = measure(|| {
+        network_system
+            .send_state(&world, client(5000), now)
+            .unwrap()
+    });
+    assert_eq!(sent, 1);
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn the_allocator_counts() {
+    let (allocations, _) = measure(|| {
+        std::hint::black_box(vec![0u8; 16]);
+    });
+    assert_eq!(allocations, 1);
+}
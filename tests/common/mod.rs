@@ -0,0 +1,81 @@
+//! An in-process server on a [`MemoryTransport`], driven by a fake clock.
+
+use pong_server::{
+    config::Config,
+    network::{MemoryPeer, MemoryTransport, NetworkSystem},
+    protocol::Message,
+    server::Server,
+};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// A client address on loopback.
+pub fn client(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+pub struct Harness {
+    pub server: Server<MemoryTransport>,
+    peer: MemoryPeer,
+    pub now: Instant,
+}
+
+impl Harness {
+    pub fn new(config: &Config) -> Self {
+        let now = Instant::now();
+        let (transport, peer) = MemoryTransport::new();
+        let server = Server::new(config, NetworkSystem::new(transport, now), now);
+        Harness { server, peer, now }
+    }
+
+    /// Queues `message` as if `from` had sent it; the next [`Harness::advance`]
+    /// delivers it.
+    pub fn send(&self, from: SocketAddr, message: &Message) {
+        self.send_raw(from, message.to_string().as_bytes());
+    }
+
+    pub fn send_raw(&self, from: SocketAddr, bytes: &[u8]) {
+        self.peer.to_server.send((from, bytes.to_vec())).unwrap();
+    }
+
+    /// Moves the clock on by `by`, runs one server update and returns
+    /// everything the server sent, in order.
+    pub fn advance(&mut self, by: Duration) -> Vec<(SocketAddr, Message)> {
+        self.advance_raw(by)
+            .into_iter()
+            .map(|(to, bytes)| (to, Message::deserialize(&bytes).unwrap()))
+            .collect()
+    }
+
+    /// Like [`Harness::advance`], but returns the datagrams as sent.
+    pub fn advance_raw(&mut self, by: Duration) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.now += by;
+        self.server.update(self.now);
+        self.peer.from_server.try_iter().collect()
+    }
+
+    /// Sends `message` from `from` and returns the server's replies to it,
+    /// without moving the clock.
+    pub fn request(&mut self, from: SocketAddr, message: &Message) -> Vec<(SocketAddr, Message)> {
+        self.send(from, message);
+        self.advance(Duration::ZERO)
+    }
+}
+
+/// The messages in `sent` that went to `to`.
+pub fn to(to: SocketAddr, sent: &[(SocketAddr, Message)]) -> Vec<Message> {
+    sent.iter()
+        .filter(|(address, _)| *address == to)
+        .map(|(_, message)| message.clone())
+        .collect()
+}
+
+pub fn join() -> Message {
+    Message::Join {
+        color: None,
+        avatar: None,
+        solo: false,
+    }
+}
@@ -0,0 +1,194 @@
+//! Long runs of random but reproducible traffic against a [`Server`]: joins,
+//! leaves, paddle input, pings, stats requests, legacy clients and garbage
+//! from a handful of peers, with the clock moving on by random steps in
+//! between.
+//!
+//! The server audits its invariants as it goes, panicking in debug builds, and
+//! every reply must decode, as a message or as a legacy `x y w h` datagram. A
+//! failing run is cut down to a short sequence of actions that still fails,
+//! which is reported along with its seed.
+//!
+//! [`Server`]: pong_server::server::Server
+
+// Only the harness is needed here.
+#[allow(dead_code)]
+mod common;
+
+use common::{client, Harness};
+use pong_server::{
+    config::Config,
+    physics::{DEFAULT_FIELD_WIDTH, PADDLE_MARGIN, PADDLE_WIDTH},
+    protocol::Message,
+};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    time::Duration,
+};
+
+const PEERS: u16 = 5;
+const ACTIONS_PER_RUN: usize = 400;
+
+/// Knuth's MMIX linear congruential generator; plenty for picking actions,
+/// and the same on every platform.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 33) as u32
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        self.next() % n
+    }
+
+    /// A float in `min..max`.
+    fn between(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.below(1 << 16) as f32 / (1 << 16) as f32
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    Send(u16, Message),
+    Garbage(u16, &'static [u8]),
+    /// A legacy client's move to this height.
+    LegacyMove(u16, f32),
+    Advance(Duration),
+}
+
+const GARBAGE: [&[u8]; 7] = [
+    b"",
+    b"1",
+    b"join",
+    b"2 join",
+    b"1 bogus",
+    b"1 update NaN 3",
+    b"1 join color=zz \xff",
+];
+
+fn action(rng: &mut Lcg) -> Action {
+    let peer = 5000 + rng.below(PEERS as u32) as u16;
+    let message = match rng.below(20) {
+        0..=1 => Message::Join {
+            color: (rng.below(2) == 0).then(|| rng.below(0x100_0000)),
+            avatar: (rng.below(2) == 0).then(|| rng.below(20) as u8),
+            solo: rng.below(2) == 0,
+        },
+        2 => Message::Leave,
+        3..=8 => Message::PlayerUpdate {
+            x: if rng.below(2) == 0 {
+                PADDLE_MARGIN
+            } else {
+                DEFAULT_FIELD_WIDTH - PADDLE_MARGIN - PADDLE_WIDTH
+            },
+            y: rng.between(-50., 650.),
+        },
+        9..=11 => Message::Axis {
+            value: rng.between(-1.5, 1.5),
+        },
+        12 => Message::Ping {
+            client_time: rng.next() as u64,
+        },
+        13 => Message::Stats,
+        14 => return Action::Garbage(peer, GARBAGE[rng.below(GARBAGE.len() as u32) as usize]),
+        15 => return Action::LegacyMove(peer, rng.between(-50., 650.)),
+        // Now and then everyone goes quiet for long enough to time out.
+        16 => return Action::Advance(Duration::from_millis(rng.below(5000) as u64)),
+        _ => return Action::Advance(Duration::from_millis(rng.below(120) as u64)),
+    };
+    Action::Send(peer, message)
+}
+
+/// Short timeouts and matches, a bot and few rooms, so a run reaches every
+/// way a player or a room can come and go.
+fn config() -> Config {
+    Config {
+        winning_score: 2,
+        player_timeout: Duration::from_secs(3),
+        lobby_timeout: Duration::from_secs(4),
+        bot_wait: Some(Duration::from_secs(1)),
+        max_rooms: 2,
+        ..Config::default()
+    }
+}
+
+/// Whether `bytes` is a message, or what a legacy client is sent: groups of
+/// four floats.
+fn decodes(bytes: &[u8]) -> bool {
+    if Message::deserialize(bytes).is_ok() {
+        return true;
+    }
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+    let floats: Vec<_> = text.split(' ').map(|part| part.parse::<f32>()).collect();
+    floats.len() % 4 == 0 && floats.iter().all(|float| float.is_ok())
+}
+
+fn advance(harness: &mut Harness, by: Duration) {
+    for (to, bytes) in harness.advance_raw(by) {
+        assert!(decodes(&bytes), "{} was sent {:?}", to, bytes);
+    }
+}
+
+/// Plays `actions` against a fresh server. Panics on a violated invariant or
+/// a reply that doesn't decode.
+fn play(actions: &[Action]) {
+    let mut harness = Harness::new(&config());
+    harness
+        .server
+        .set_logger(pong_server::log::Logger::new(|_| {}));
+    for action in actions {
+        match action {
+            Action::Send(port, message) => harness.send(client(*port), message),
+            Action::Garbage(port, bytes) => harness.send_raw(client(*port), bytes),
+            Action::LegacyMove(port, y) => {
+                let x = if port % 2 == 0 { 20 } else { 760 };
+                let update = format!("{} {} 20 100", x, y);
+                harness.send_raw(client(*port), update.as_bytes());
+            }
+            Action::Advance(by) => advance(&mut harness, *by),
+        }
+    }
+    advance(&mut harness, Duration::ZERO);
+    assert_eq!(harness.server.invariant_violations(), 0);
+}
+
+fn fails(actions: &[Action]) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(|| play(actions))).is_err()
+}
+
+/// Drops actions from a failing run for as long as it keeps failing.
+fn shrink(mut actions: Vec<Action>) -> Vec<Action> {
+    let mut i = 0;
+    while i < actions.len() {
+        let mut shorter = actions.clone();
+        shorter.remove(i);
+        if fails(&shorter) {
+            actions = shorter;
+        } else {
+            i += 1;
+        }
+    }
+    actions
+}
+
+#[test]
+fn random_play_keeps_every_invariant() {
+    for seed in 0..32 {
+        let mut rng = Lcg(seed);
+        let actions: Vec<Action> = (0..ACTIONS_PER_RUN).map(|_| action(&mut rng)).collect();
+        if fails(&actions) {
+            panic!(
+                "seed {} fails; smallest failing run: {:?}",
+                seed,
+                shrink(actions)
+            );
+        }
+    }
+}
@@ -0,0 +1,842 @@
+//! Whole games played against a [`Server`] over its in-memory transport.
+//!
+//! [`Server`]: pong_server::server::Server
+
+mod common;
+
+use common::{client, join, to, Harness};
+use pong_server::{
+    config::Config,
+    log::Logger,
+    physics::{DEFAULT_FIELD_WIDTH, NOMINAL_DT, PADDLE_MARGIN, PADDLE_WIDTH},
+    protocol::{EntityKind, EntityState, Message, ProtocolError, MAX_REPORTED_SCORES},
+};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+const FRAME: Duration = Duration::from_millis(50);
+
+fn states(messages: &[Message]) -> Vec<(u64, Vec<EntityState>)> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::State {
+                sequence, entities, ..
+            } => Some((*sequence, entities.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn a_full_game_reaches_a_goal() {
+    let mut harness = Harness::new(&Config::default());
+    let left = client(5000);
+    let right = client(5001);
+
+    let sent = harness.request(left, &join());
+    let left_paddle = match to(left, &sent).first() {
+        Some(Message::JoinAck { paddle, .. }) => *paddle,
+        other => panic!("expected a join ack, got {:?}", other),
+    };
+    let sent = harness.request(right, &join());
+    let right_paddle = match to(right, &sent).first() {
+        Some(Message::JoinAck { paddle, .. }) => *paddle,
+        other => panic!("expected a join ack, got {:?}", other),
+    };
+    assert_eq!(left_paddle.x, PADDLE_MARGIN);
+    assert_eq!(
+        right_paddle.x,
+        DEFAULT_FIELD_WIDTH - PADDLE_MARGIN - PADDLE_WIDTH
+    );
+
+    // The left player walks its paddle down while the right one stays put,
+    // well above the ball's path, so the serve goes past it.
+    let mut left_y = left_paddle.y;
+    let mut sent_to_left = Vec::new();
+    let mut sent_to_right = Vec::new();
+    for _ in 0..20 {
+        left_y += 10.;
+        harness.send(
+            left,
+            &Message::PlayerUpdate {
+                x: left_paddle.x,
+                y: left_y,
+            },
+        );
+        harness.send(
+            right,
+            &Message::PlayerUpdate {
+                x: right_paddle.x,
+                y: right_paddle.y,
+            },
+        );
+        let sent = harness.advance(FRAME);
+        sent_to_left.extend(to(left, &sent));
+        sent_to_right.extend(to(right, &sent));
+    }
+
+    // Nobody was corrected for moving within the rules.
+    assert!(!sent_to_left
+        .iter()
+        .chain(sent_to_right.iter())
+        .any(|message| matches!(message, Message::Correction { .. })));
+
+    // Each player sees the other paddle and the ball, in id order, in
+    // packets numbered in the order they were sent.
+    let left_states = states(&sent_to_left);
+    let right_states = states(&sent_to_right);
+    assert!(left_states.len() >= 8, "{}", left_states.len());
+    for (_, entities) in left_states.iter().chain(right_states.iter()) {
+        let ids: Vec<u32> = entities.iter().map(|entity| entity.id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ids);
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1].kind, EntityKind::Ball);
+    }
+    let sequences: Vec<u64> = left_states
+        .iter()
+        .chain(right_states.iter())
+        .map(|(sequence, _)| *sequence)
+        .collect();
+    let mut sorted = sequences.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    assert_eq!(sorted.len(), sequences.len());
+    assert!(left_states
+        .iter()
+        .all(|(_, entities)| entities[0].id == right_paddle.id));
+    let (_, last) = right_states.last().unwrap();
+    assert_eq!(last[0].id, left_paddle.id);
+    // The server carries the paddle on at the speed its updates imply.
+    assert!((last[0].y - left_y).abs() <= 10., "{}", last[0].y);
+    assert!(last[0].dy > 0.);
+
+    // The serve went right, past the right paddle, and scored for the left.
+    let score = Message::Score { left: 1, right: 0 };
+    assert_eq!(
+        sent_to_left.iter().filter(|&m| *m == score).count(),
+        1,
+        "{:?}",
+        sent_to_left
+    );
+    assert_eq!(sent_to_right.iter().filter(|&m| *m == score).count(), 1);
+
+    // Until the goal the ball only moved right; the new serve starts back at
+    // the center.
+    let ball_x: Vec<f32> = left_states
+        .iter()
+        .map(|(_, entities)| entities[1].x)
+        .collect();
+    let goal = ball_x
+        .windows(2)
+        .position(|pair| pair[1] < pair[0])
+        .expect("the ball was never reset");
+    assert!(ball_x[..=goal].windows(2).all(|pair| pair[1] > pair[0]));
+    assert!(ball_x[goal + 1] < DEFAULT_FIELD_WIDTH / 2. + 100.);
+}
+
+fn ticks(messages: &[Message]) -> Vec<u64> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::State { tick, .. } => Some(*tick),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn a_rooms_tick_keeps_counting_through_a_pause() {
+    let mut harness = Harness::new(&Config::default());
+    let (left, right, stand_in) = (client(5000), client(5001), client(5002));
+    harness.request(left, &join());
+    harness.request(right, &join());
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        seen.extend(ticks(&to(left, &harness.advance(FRAME))));
+    }
+
+    // The opponent leaves, pausing the match for half a second, and someone
+    // else takes the seat.
+    harness.request(right, &Message::Leave);
+    for _ in 0..10 {
+        harness.advance(FRAME);
+    }
+    harness.request(stand_in, &join());
+    let mut resumed = Vec::new();
+    for _ in 0..4 {
+        resumed.extend(ticks(&to(left, &harness.advance(FRAME))));
+    }
+
+    // The room went on ticking, 60 times a second, while paused.
+    let paused = resumed[0] - seen.last().unwrap();
+    assert!(paused >= 30, "{:?} {:?}", seen, resumed);
+    seen.extend(resumed);
+    assert!(seen.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", seen);
+}
+
+/// Moves one player's paddle 15 units every 50 ms, 300 units per second,
+/// optionally pinging 5 ms before each update, and counts the corrections.
+fn corrections_for_legal_moves(ping_first: bool) -> usize {
+    let mut harness = Harness::new(&Config::default());
+    let player = client(5000);
+    let paddle = match to(player, &harness.request(player, &join())).first() {
+        Some(Message::JoinAck { paddle, .. }) => *paddle,
+        other => panic!("expected a join ack, got {:?}", other),
+    };
+
+    let mut y = paddle.y;
+    let mut corrections = 0;
+    for _ in 0..20 {
+        if ping_first {
+            harness.send(player, &Message::Ping { client_time: 1 });
+        }
+        let mut sent = harness.advance(Duration::from_millis(45));
+        y += 15.;
+        harness.send(player, &Message::PlayerUpdate { x: paddle.x, y });
+        sent.extend(harness.advance(Duration::from_millis(5)));
+        corrections += to(player, &sent)
+            .iter()
+            .filter(|message| matches!(message, Message::Correction { .. }))
+            .count();
+    }
+    corrections
+}
+
+#[test]
+fn legal_moves_are_not_corrected() {
+    assert_eq!(corrections_for_legal_moves(false), 0);
+}
+
+#[test]
+fn other_packets_do_not_shorten_the_time_a_paddle_had_to_move() {
+    assert_eq!(corrections_for_legal_moves(true), 0);
+}
+
+#[test]
+fn a_solo_match_closes_when_the_player_leaves() {
+    let config = Config {
+        bot_wait: Some(Duration::from_secs(1)),
+        ..Config::default()
+    };
+    let mut harness = Harness::new(&config);
+    let player = client(5000);
+    let solo = Message::Join {
+        color: None,
+        avatar: None,
+        solo: true,
+    };
+    harness.request(player, &solo);
+    harness.advance(FRAME);
+    assert_eq!(harness.server.rooms().iter().count(), 1);
+
+    harness.send(player, &Message::Leave);
+    harness.advance(FRAME);
+    assert_eq!(harness.server.rooms().iter().count(), 0);
+    assert_eq!(harness.server.rooms().room_of(player), None);
+}
+
+#[test]
+fn stats_break_players_and_packets_down_by_peer_class() {
+    let mut harness = Harness::new(&Config::default());
+    let loopback = client(5000);
+    let private = "192.168.1.10:5000".parse().unwrap();
+    let public = "[2606:4700::1111]:5000".parse().unwrap();
+    harness.request(loopback, &join());
+    harness.request(private, &join());
+    harness.request(private, &Message::Ping { client_time: 1 });
+
+    let sent = harness.request(public, &Message::Stats);
+    match to(public, &sent).as_slice() {
+        [Message::StatsReport {
+            players,
+            packets_received,
+            loopback,
+            private,
+            public,
+            ..
+        }] => {
+            assert_eq!((*players, *packets_received), (2, 4));
+            assert_eq!((loopback.players, loopback.packets_received), (1, 1));
+            assert_eq!((private.players, private.packets_received), (1, 2));
+            assert_eq!((public.players, public.packets_received), (0, 1));
+        }
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+}
+
+/// The ids of every entity in `messages`: join acks and state packets.
+fn entity_ids(messages: &[Message]) -> Vec<u32> {
+    messages
+        .iter()
+        .flat_map(|message| match message {
+            Message::JoinAck { paddle, .. } => vec![paddle.id],
+            Message::State { entities, .. } => entities.iter().map(|entity| entity.id).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[test]
+fn entities_that_leave_are_despawned_once_and_never_reused() {
+    let mut harness = Harness::new(&Config::default());
+    let stayer = client(5000);
+    let leaver = client(5001);
+    let mut seen = Vec::new();
+    seen.extend(to(stayer, &harness.request(stayer, &join())));
+    harness.request(leaver, &join());
+    for _ in 0..4 {
+        seen.extend(to(stayer, &harness.advance(FRAME)));
+    }
+    let before = entity_ids(&seen);
+    assert!(before.contains(&1) && before.contains(&2), "{:?}", before);
+
+    // The leaver's paddle and the ball go; each is announced exactly once.
+    harness.send(leaver, &Message::Leave);
+    let mut after_leave = Vec::new();
+    for _ in 0..4 {
+        after_leave.extend(to(stayer, &harness.advance(FRAME)));
+    }
+    let mut despawned: Vec<u32> = after_leave
+        .iter()
+        .filter_map(|message| match message {
+            Message::Despawn { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    despawned.sort_unstable();
+    assert_eq!(despawned, [1, 2]);
+    assert!(to(leaver, &harness.advance(FRAME)).is_empty());
+
+    // The next opponent and ball get fresh ids.
+    let newcomer = client(5002);
+    let mut seen = to(newcomer, &harness.request(newcomer, &join()));
+    for _ in 0..4 {
+        let sent = harness.advance(FRAME);
+        seen.extend(to(stayer, &sent));
+        seen.extend(to(newcomer, &sent));
+    }
+    let after = entity_ids(&seen);
+    assert!(after.contains(&3) && after.contains(&4), "{:?}", after);
+    assert!(
+        after.iter().all(|id| !despawned.contains(id)),
+        "{:?}",
+        after
+    );
+    assert!(!seen
+        .iter()
+        .any(|message| matches!(message, Message::Despawn { .. })));
+}
+
+#[test]
+fn despawns_only_reach_players_who_saw_the_entity() {
+    let mut harness = Harness::new(&Config::default());
+    let solo = client(5000);
+    let newcomer = client(5001);
+    let join_solo = Message::Join {
+        color: None,
+        avatar: None,
+        solo: true,
+    };
+    harness.request(solo, &join_solo);
+    let mut seen = Vec::new();
+    for _ in 0..2 {
+        seen.extend(to(solo, &harness.advance(FRAME)));
+    }
+    let ids = entity_ids(&seen);
+    assert!(ids.contains(&1) && ids.contains(&2), "{:?}", ids);
+
+    // The bot and its ball make way for the newcomer within one update.
+    let sent = harness.request(newcomer, &join());
+    let despawned = |messages: Vec<Message>| -> Vec<u32> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Despawn { id } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(despawned(to(solo, &sent)), [1, 2]);
+    assert_eq!(despawned(to(newcomer, &sent)), []);
+}
+
+#[test]
+fn players_see_each_others_appearance() {
+    let mut harness = Harness::new(&Config::default());
+    let left = client(5000);
+    let right = client(5001);
+
+    // Invalid hex is dropped, so the left player gets the left default.
+    harness.send_raw(left, b"1 join color=zz9900 avatar=4");
+    let sent = harness.advance(Duration::ZERO);
+    let left_color = match to(left, &sent).first() {
+        Some(Message::JoinAck { color, avatar, .. }) => {
+            assert_eq!(*avatar, 4);
+            *color
+        }
+        other => panic!("expected a join ack, got {:?}", other),
+    };
+    assert_eq!(left_color, 0x3399FF);
+
+    // The right player picks the same color and is nudged off it.
+    let sent = harness.request(
+        right,
+        &Message::Join {
+            color: Some(left_color),
+            avatar: Some(7),
+            solo: false,
+        },
+    );
+    let right_color = match to(right, &sent).first() {
+        Some(Message::JoinAck { color, .. }) => *color,
+        other => panic!("expected a join ack, got {:?}", other),
+    };
+    assert_ne!(right_color, left_color);
+
+    assert!(to(left, &sent).contains(&Message::Opponent {
+        color: right_color,
+        avatar: 7,
+    }));
+    assert!(to(right, &sent).contains(&Message::Opponent {
+        color: left_color,
+        avatar: 4,
+    }));
+}
+
+#[test]
+fn join_acks_lock_each_paddle_at_its_own_x() {
+    let mut harness = Harness::new(&Config::default());
+    let right_x = DEFAULT_FIELD_WIDTH - PADDLE_MARGIN - PADDLE_WIDTH;
+    for (port, x) in [(5000, PADDLE_MARGIN), (5001, right_x)] {
+        let player = client(port);
+        match to(player, &harness.request(player, &join())).first() {
+            Some(Message::JoinAck { paddle, lock_x, .. }) => {
+                assert_eq!((*lock_x, paddle.x), (x, x));
+            }
+            other => panic!("expected a join ack, got {:?}", other),
+        }
+
+        // Trying to move off the locked x is answered with a correction.
+        let sent = harness.request(player, &Message::PlayerUpdate { x: 400., y: 100. });
+        assert_eq!(to(player, &sent), [Message::Correction { x, y: 100. }]);
+    }
+}
+
+#[test]
+fn join_acks_state_the_axis_response() {
+    let config = Config {
+        axis_dead_zone: 0.125,
+        axis_curve: 1.375,
+        ..Config::default()
+    };
+    let mut harness = Harness::new(&config);
+    let player = client(5000);
+    match to(player, &harness.request(player, &join())).first() {
+        Some(Message::JoinAck {
+            dead_zone, curve, ..
+        }) => assert_eq!((*dead_zone, *curve), (0.125, 1.375)),
+        other => panic!("expected a join ack, got {:?}", other),
+    }
+}
+
+/// The number of players in every room, and whether `peer` is in one.
+fn players(harness: &Harness, peer: SocketAddr) -> (usize, bool) {
+    let rooms = harness.server.rooms();
+    let players = rooms
+        .iter()
+        .map(|(_, world)| world.player_addresses().count())
+        .sum();
+    (players, rooms.room_of(peer).is_some())
+}
+
+#[test]
+fn pings_echo_the_client_time_without_joining() {
+    let mut harness = Harness::new(&Config::default());
+    let peer = client(5000);
+    harness.now += Duration::from_millis(1500);
+
+    let sent = harness.request(
+        peer,
+        &Message::Ping {
+            client_time: u64::MAX,
+        },
+    );
+    assert_eq!(
+        to(peer, &sent),
+        [Message::Pong {
+            client_time: u64::MAX,
+            server_time_ms: 1500,
+        }]
+    );
+    assert_eq!(players(&harness, peer), (0, false));
+    assert_eq!(harness.server.rooms().iter().count(), 0);
+}
+
+#[test]
+fn stats_work_for_strangers_while_the_room_is_full() {
+    let mut harness = Harness::new(&Config::default());
+    harness.request(client(5000), &join());
+    harness.request(client(5001), &join());
+    harness.advance(FRAME);
+
+    let stranger = client(6000);
+    let sent = harness.request(stranger, &Message::Ping { client_time: 7 });
+    assert!(matches!(
+        to(stranger, &sent).as_slice(),
+        [Message::Pong { client_time: 7, .. }]
+    ));
+
+    let sent = harness.request(stranger, &Message::Stats);
+    match to(stranger, &sent).as_slice() {
+        [Message::StatsReport {
+            rooms,
+            players,
+            balls_in_play,
+            scores,
+            ..
+        }] => {
+            assert_eq!((*rooms, *players, *balls_in_play), (1, 2, 1));
+            assert_eq!(scores, &[(0, 0)]);
+        }
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+
+    // Neither request took a seat or opened a room.
+    assert_eq!(players(&harness, stranger), (2, false));
+    assert_eq!(harness.server.rooms().iter().count(), 1);
+}
+
+#[test]
+fn joins_past_the_room_limit_are_refused() {
+    let config = Config {
+        max_rooms: 1,
+        ..Config::default()
+    };
+    let mut harness = Harness::new(&config);
+    let left = client(5000);
+    let right = client(5001);
+    harness.request(left, &join());
+    harness.request(right, &join());
+
+    let late = client(5002);
+    let sent = harness.request(late, &join());
+    assert_eq!(to(late, &sent), [Message::ServerAtCapacity]);
+    assert_eq!(harness.server.rooms().iter().count(), 1);
+
+    // The match already under way carries on.
+    let sent = harness.advance(FRAME * 2);
+    assert!(!states(&to(left, &sent)).is_empty());
+    assert!(to(late, &sent).is_empty());
+
+    let sent = harness.request(late, &Message::Stats);
+    match to(late, &sent).as_slice() {
+        [Message::StatsReport {
+            rooms, max_rooms, ..
+        }] => assert_eq!((*rooms, *max_rooms), (1, 1)),
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+
+    // Once the match empties its room closes and the next join gets one.
+    harness.request(left, &Message::Leave);
+    harness.request(right, &Message::Leave);
+    let sent = harness.request(late, &join());
+    assert!(matches!(
+        to(late, &sent).first(),
+        Some(Message::JoinAck { .. })
+    ));
+}
+
+#[test]
+fn stats_fit_in_a_datagram_with_every_room_open() {
+    let mut harness = Harness::new(&Config::default());
+    let max_rooms = Config::default().max_rooms;
+    for port in 0..max_rooms as u16 * 2 {
+        harness.request(client(5000 + port), &join());
+    }
+    assert_eq!(harness.server.rooms().iter().count(), max_rooms as usize);
+
+    let stranger = client(6000);
+    let sent = harness.request(stranger, &Message::Stats);
+    match to(stranger, &sent).as_slice() {
+        [Message::StatsReport { rooms, scores, .. }] => {
+            assert_eq!(*rooms, max_rooms);
+            assert_eq!(scores.len(), MAX_REPORTED_SCORES);
+        }
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+}
+
+#[test]
+fn join_acks_and_stats_name_the_server_build() {
+    let mut harness = Harness::new(&Config::default());
+    let player = client(5000);
+    match to(player, &harness.request(player, &join())).first() {
+        Some(Message::JoinAck { server, .. }) => {
+            assert!(!server.is_empty());
+            assert_eq!(server, pong_server::BUILD_INFO);
+        }
+        other => panic!("expected a join ack, got {:?}", other),
+    }
+
+    match to(player, &harness.request(player, &Message::Stats)).first() {
+        Some(Message::StatsReport { server, .. }) => {
+            assert_eq!(server, pong_server::BUILD_INFO);
+        }
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+
+    // Version, git commit and build profile are all filled in.
+    let (version, build) = pong_server::BUILD_INFO.split_once('+').unwrap();
+    let (commit, profile) = build.split_once('.').unwrap();
+    assert_eq!(version, env!("CARGO_PKG_VERSION"));
+    assert!(!commit.is_empty() && !profile.is_empty());
+}
+
+#[test]
+fn hosts_can_collect_the_log() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::new(&Config::default());
+    let sink = Arc::clone(&lines);
+    harness.server.set_logger(Logger::new(move |line| {
+        sink.lock().unwrap().push(line.to_string())
+    }));
+
+    let player = client(5000);
+    harness.request(player, &join());
+    harness.send(player, &Message::Leave);
+    harness.advance(FRAME);
+    harness.send_raw(player, b"garbage");
+    harness.advance(FRAME);
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines[0], "Room 0 opened");
+    assert!(lines[1].starts_with("Player joined room 0"), "{:?}", lines);
+    assert!(
+        lines[2].starts_with("Player from loopback peer left"),
+        "{:?}",
+        lines
+    );
+    assert_eq!(lines[3], "Room 0 closed");
+    assert!(lines[4].starts_with("Rejected packet from"), "{:?}", lines);
+    assert_eq!(lines.len(), 5);
+}
+
+/// Where the ball is across the only room in `harness`.
+fn ball_x(harness: &Harness) -> f32 {
+    let (_, world) = harness.server.rooms().iter().next().unwrap();
+    world
+        .render_components
+        .iter()
+        .find(|renderable| renderable.is_ball())
+        .unwrap()
+        .x
+}
+
+#[test]
+fn each_tick_once_advances_exactly_one_tick() {
+    let mut harness = Harness::new(&Config::default());
+    harness.request(client(5000), &join());
+    harness.request(client(5001), &join());
+    let now = harness.now;
+    harness.server.tick_once(now);
+    let served = ball_x(&harness);
+
+    // However little or much time the host says has passed.
+    let step = Config::default().ball_speed_ups * NOMINAL_DT;
+    for (ticks, later) in [(1., Duration::ZERO), (2., Duration::from_secs(5))] {
+        harness.server.tick_once(now + later);
+        assert!((ball_x(&harness) - served - ticks * step).abs() < 0.01);
+    }
+}
+
+#[test]
+fn run_until_returns_the_final_stats_once_shut_down() {
+    let mut harness = Harness::new(&Config::default());
+    harness.send(client(5000), &join());
+    harness.send(client(5001), &join());
+
+    let shutdown = AtomicBool::new(false);
+    let mut now = harness.now;
+    let mut updates = 0;
+    let stats = harness.server.run_until(&shutdown, || {
+        updates += 1;
+        if updates == 10 {
+            shutdown.store(true, Ordering::Relaxed);
+        }
+        now += FRAME;
+        now
+    });
+    match stats {
+        Message::StatsReport {
+            rooms,
+            players,
+            balls_in_play,
+            uptime_secs,
+            ..
+        } => assert_eq!((rooms, players, balls_in_play, uptime_secs), (1, 2, 1, 0)),
+        other => panic!("expected a stats report, got {:?}", other),
+    }
+}
+
+#[test]
+fn a_host_can_run_the_server_on_its_own_thread() {
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&Harness::new(&Config::default()).server);
+}
+
+/// Every datagram of the opening of a two-player game, as sent. Which peer
+/// owns which entity is the server's own bookkeeping, so however ownership
+/// is keyed, this must not change.
+#[test]
+fn the_classic_game_goes_out_byte_for_byte() {
+    let mut harness = Harness::new(&Config::default());
+    let (left, right) = (client(5000), client(5001));
+    let mut sent = Vec::new();
+    harness.send(left, &join());
+    harness.send(right, &join());
+    sent.extend(harness.advance_raw(Duration::ZERO));
+    for frame in 1..=4 {
+        let y = 100. + 10. * frame as f32;
+        harness.send(
+            left,
+            &Message::PlayerUpdate {
+                x: PADDLE_MARGIN,
+                y,
+            },
+        );
+        sent.extend(harness.advance_raw(FRAME));
+    }
+
+    let transcript: Vec<String> = sent
+        .iter()
+        .map(|(to, bytes)| {
+            let text = String::from_utf8(bytes.clone()).unwrap();
+            format!(
+                "{} {}",
+                to.port(),
+                text.replace(pong_server::BUILD_INFO, "BUILD")
+            )
+        })
+        .collect();
+    assert_eq!(
+        transcript,
+        [
+            "5000 1 join_ack 0 paddle 20.00 100.00 20.00 100.00 0.00 0.00 color=3399FF avatar=0 lock_x=20.00 dead_zone=0.1 curve=2 room=0 server=BUILD",
+            "5000 1 score 0 0",
+            "5001 1 join_ack 1 paddle 760.00 100.00 20.00 100.00 0.00 0.00 color=FF5533 avatar=0 lock_x=760.00 dead_zone=0.1 curve=2 room=0 server=BUILD",
+            "5000 1 opponent color=FF5533 avatar=0",
+            "5001 1 opponent color=3399FF avatar=0",
+            "5000 1 score 0 0",
+            "5001 1 score 0 0",
+            "5000 1 state 1 5 100 1 paddle 760.00 100.00 20.00 100.00 0.00 0.00 2 ball 480.00 300.00 20.00 20.00 1200.00 0.00",
+            "5001 1 state 2 5 100 0 paddle 20.00 133.33 20.00 100.00 0.00 266.67 2 ball 480.00 300.00 20.00 20.00 1200.00 0.00",
+            "5000 1 state 3 11 200 1 paddle 760.00 100.00 20.00 100.00 0.00 0.00 2 ball 600.00 300.00 20.00 20.00 1200.00 0.00",
+            "5001 1 state 4 11 200 0 paddle 20.00 150.00 20.00 100.00 0.00 200.00 2 ball 600.00 300.00 20.00 20.00 1200.00 0.00",
+        ]
+    );
+}
+
+fn text_to(to: SocketAddr, sent: &[(SocketAddr, Vec<u8>)]) -> Vec<String> {
+    sent.iter()
+        .filter(|(address, _)| *address == to)
+        .map(|(_, bytes)| String::from_utf8(bytes.clone()).unwrap())
+        .collect()
+}
+
+/// A client of the unversioned protocol the server first spoke, playing a
+/// current one. It gets exactly the bytes it always did and nothing else.
+#[test]
+fn legacy_clients_still_play_byte_for_byte() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::new(&Config::default());
+    let sink = Arc::clone(&lines);
+    harness.server.set_logger(Logger::new(move |line| {
+        sink.lock().unwrap().push(line.to_string())
+    }));
+    let (old, new) = (client(5000), client(5001));
+
+    harness.send_raw(old, b"join");
+    let mut to_old = text_to(old, &harness.advance_raw(Duration::ZERO));
+    harness.send(new, &join());
+    let sent = harness.advance_raw(Duration::ZERO);
+    to_old.extend(text_to(old, &sent));
+    assert!(matches!(
+        Message::deserialize(&sent[0].1),
+        Ok(Message::JoinAck { .. })
+    ));
+
+    let mut last_seen_by_new = None;
+    for frame in 1..=4 {
+        let update = format!("20 {} 20 100", 100 + 10 * frame);
+        harness.send_raw(old, update.as_bytes());
+        harness.send(new, &Message::PlayerUpdate { x: 760., y: 100. });
+        let sent = harness.advance_raw(FRAME);
+        to_old.extend(text_to(old, &sent));
+        for (_, bytes) in sent.iter().filter(|(to, _)| *to == new) {
+            if let Ok(Message::State { entities, .. }) = Message::deserialize(bytes) {
+                last_seen_by_new = Some(entities[0]);
+            }
+        }
+    }
+    assert_eq!(
+        to_old,
+        [
+            "20 100 20 100",
+            "760 100 20 100",
+            "760 100 20 100 420 300 20 20",
+            "760 100 20 100 480 300 20 20",
+            "760 100 20 100 540 300 20 20",
+        ]
+    );
+    // The current client sees the old one's paddle move.
+    assert!(last_seen_by_new.unwrap().y > 120.);
+
+    let deprecations = lines
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line.contains("deprecated"))
+        .count();
+    assert_eq!(deprecations, 1);
+}
+
+#[test]
+fn legacy_clients_are_told_why_once_turned_off() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let config = Config {
+        legacy_clients: false,
+        ..Config::default()
+    };
+    let mut harness = Harness::new(&config);
+    let sink = Arc::clone(&lines);
+    harness.server.set_logger(Logger::new(move |line| {
+        sink.lock().unwrap().push(line.to_string())
+    }));
+    let old = client(5000);
+
+    for datagram in [&b"join"[..], b"20 110 20 100"] {
+        harness.send_raw(old, datagram);
+        let sent = harness.advance(Duration::ZERO);
+        assert_eq!(
+            to(old, &sent),
+            [Message::Error {
+                reason: ProtocolError::Unversioned.to_string()
+            }]
+        );
+    }
+    assert_eq!(harness.server.rooms().iter().count(), 0);
+    let rejections = lines
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line.ends_with("legacy clients are off"))
+        .count();
+    assert_eq!(rejections, 2);
+}